@@ -1,79 +1,165 @@
 use aoc_runner_derive::aoc;
-use std::collections::hash_map::Entry;
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
 
-struct Communicator {
+/// Watches a datastream one byte at a time for windows where every byte is different
+pub struct MarkerDetector {
     window_size: usize,
-    char_count: usize,
-    buffer: VecDeque<char>,
-    chars: HashMap<char, usize>,
+    bytes_seen: usize,
+    buffer: VecDeque<u8>,
+    counts: [usize; 256],
+    distinct: usize,
 }
 
-impl Communicator {
-    fn new(window_size: usize) -> Self {
-        Communicator {
+impl MarkerDetector {
+    pub fn new(window_size: usize) -> Self {
+        assert!(window_size > 0, "Marker window must hold at least one byte");
+
+        MarkerDetector {
             window_size,
-            char_count: 0,
-            buffer: VecDeque::with_capacity(4),
-            chars: HashMap::with_capacity(4),
+            bytes_seen: 0,
+            buffer: VecDeque::with_capacity(window_size),
+            counts: [0; 256],
+            distinct: 0,
         }
     }
 
-    fn remove_char(&mut self) {
-        let removed = self.buffer.pop_front().unwrap();
-        if let Entry::Occupied(mut occupied) = self.chars.entry(removed) {
-            *occupied.get_mut() -= 1;
-            if *occupied.get() == 0 {
-                occupied.remove();
+    /// Adds the next byte of the stream, returning the number of bytes seen so far if the most
+    /// recent window is a marker
+    pub fn push(&mut self, byte: u8) -> Option<usize> {
+        self.bytes_seen += 1;
+
+        if self.buffer.len() == self.window_size {
+            let removed = self.buffer.pop_front().unwrap();
+            self.counts[removed as usize] -= 1;
+            if self.counts[removed as usize] == 0 {
+                self.distinct -= 1;
             }
         }
-    }
-
-    fn process_char(&mut self, chr: char) {
-        self.char_count += 1;
 
-        if self.buffer.len() == self.window_size {
-            self.remove_char();
+        self.buffer.push_back(byte);
+        self.counts[byte as usize] += 1;
+        if self.counts[byte as usize] == 1 {
+            self.distinct += 1;
         }
-        self.buffer.push_back(chr);
-        *self.chars.entry(chr).or_default() += 1;
+
+        (self.distinct == self.window_size).then_some(self.bytes_seen)
     }
+}
 
-    fn has_start_marker(&self) -> bool {
-        self.chars.len() == self.window_size
+/// Iterator over every marker position in a byte stream, see `markers`
+pub struct Markers<I> {
+    bytes: I,
+    detector: MarkerDetector,
+}
+
+impl<I> Iterator for Markers<I>
+where
+    I: Iterator<Item = u8>,
+{
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        let detector = &mut self.detector;
+        self.bytes.by_ref().find_map(|byte| detector.push(byte))
     }
+}
 
-    fn chars_seen(&self) -> usize {
-        self.char_count
+/// Positions (counted in bytes from the start, through the end of the window) of every window
+/// of `window_size` distinct bytes. Windows overlap, so one long run of distinct bytes reports
+/// a marker at each position.
+pub fn markers<I>(bytes: I, window_size: usize) -> Markers<I::IntoIter>
+where
+    I: IntoIterator<Item = u8>,
+{
+    Markers {
+        bytes: bytes.into_iter(),
+        detector: MarkerDetector::new(window_size),
     }
 }
 
-#[aoc(day6, part1)]
-fn solve_part1(input: &str) -> usize {
-    let mut comm = Communicator::new(4);
+pub fn first_marker<I>(bytes: I, window_size: usize) -> Option<usize>
+where
+    I: IntoIterator<Item = u8>,
+{
+    markers(bytes, window_size).next()
+}
 
-    for chr in input.chars() {
-        comm.process_char(chr);
+/// Like `first_marker`, but reads the stream, stopping as soon as a marker is found. Only the
+/// bytes up to the end of the marker are consumed, so the rest can be read afterwards. The
+/// position is counted from wherever the reader was when this was called.
+pub fn first_marker_in<R: BufRead>(mut reader: R, window_size: usize) -> io::Result<Option<usize>> {
+    let mut detector = MarkerDetector::new(window_size);
+
+    loop {
+        let (used, found) = {
+            let buffer = reader.fill_buf()?;
+            if buffer.is_empty() {
+                return Ok(None);
+            }
 
-        if comm.has_start_marker() {
-            return comm.chars_seen();
+            match buffer
+                .iter()
+                .position(|&byte| detector.push(byte).is_some())
+            {
+                Some(index) => (index + 1, true),
+                None => (buffer.len(), false),
+            }
+        };
+
+        reader.consume(used);
+        if found {
+            return Ok(Some(detector.bytes_seen));
         }
     }
+}
+
+/// Like `markers`, but reads the whole stream
+pub fn all_markers_in<R: Read>(reader: R, window_size: usize) -> io::Result<Vec<usize>> {
+    let mut detector = MarkerDetector::new(window_size);
+    let mut positions = Vec::new();
+
+    for byte in BufReader::new(reader).bytes() {
+        positions.extend(detector.push(byte?));
+    }
+
+    Ok(positions)
+}
 
-    0
+#[aoc(day6, part1)]
+fn solve_part1(input: &str) -> usize {
+    first_marker(input.bytes(), 4).expect("No start-of-packet marker")
 }
 
 #[aoc(day6, part2)]
 fn solve_part2(input: &str) -> usize {
-    let mut comm = Communicator::new(14);
+    first_marker(input.bytes(), 14).expect("No start-of-message marker")
+}
 
-    for chr in input.chars() {
-        comm.process_char(chr);
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        if comm.has_start_marker() {
-            return comm.chars_seen();
-        }
-    }
+    #[test]
+    fn test_markers() {
+        let stream = "mjqjpqmgbljsphdztnvjfqwrcgsmlb";
+
+        assert_eq!(first_marker(stream.bytes(), 4), Some(7));
+        assert_eq!(first_marker(stream.bytes(), 14), Some(19));
 
-    0
+        let mut reader = stream.as_bytes();
+        assert_eq!(first_marker_in(&mut reader, 4).unwrap(), Some(7));
+        assert_eq!(reader, &stream.as_bytes()[7..]);
+        assert_eq!(first_marker_in(&mut reader, 14).unwrap(), Some(18));
+
+        assert_eq!(first_marker("aaaa".bytes(), 2), None);
+        assert_eq!(
+            markers("abcabbc".bytes(), 3).collect::<Vec<_>>(),
+            vec![3, 4, 5]
+        );
+        assert_eq!(
+            all_markers_in("abcabbc".as_bytes(), 3).unwrap(),
+            vec![3, 4, 5]
+        );
+    }
 }
@@ -1,145 +1,529 @@
 use aoc_runner_derive::aoc;
 
-enum Entry {
-    Directory(Directory),
-    File(File),
+const ROOT: usize = 0;
+
+const SMALL_DIRECTORY_LIMIT: usize = 100_000;
+
+/// The handheld device from the puzzle
+pub const DEVICE: Disk = Disk {
+    capacity: 70_000_000,
+    required: 30_000_000,
+};
+
+/// A disk's total size and how much of it must be free for an update to run
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disk {
+    pub capacity: usize,
+    pub required: usize,
 }
 
-struct Directory {
-    name: String,
-    children: Vec<Entry>,
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CleanupError {
+    /// The transcript describes more data than fits on the disk
+    OverCapacity { used: usize, capacity: usize },
+    /// The update needs more space than the whole disk has
+    UpdateTooLarge { required: usize, capacity: usize },
 }
 
-impl Directory {
-    fn size(&self) -> usize {
-        self.children.iter().map(Entry::size).sum()
-    }
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TranscriptError {
+    /// A `$` line that isn't `cd <path>` or `ls`
+    UnknownCommand { line: usize },
+    /// Output that doesn't follow an `ls`, or isn't `dir <name>` / `<size> <name>`
+    UnexpectedOutput { line: usize },
+    /// `cd` into something that is a file
+    NotADirectory { line: usize, path: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Directory,
+    File,
 }
 
-struct File {
+/// A child of a directory, as returned by `FileSystem::list`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Listing {
+    pub name: String,
+    pub kind: EntryKind,
+    pub size: usize,
+}
+
+struct Node {
+    name: String,
+    parent: usize,
+    kind: EntryKind,
+    /// For directories, the total size of everything inside, kept up to date as files are added
     size: usize,
+    children: Vec<usize>,
 }
 
-impl Entry {
-    fn size(&self) -> usize {
-        match self {
-            Entry::Directory(dir) => dir.size(),
-            Entry::File(File { size }) => *size,
+/// The filesystem reconstructed from a terminal transcript, stored as a tree of nodes where the
+/// root is its own parent
+pub struct FileSystem {
+    nodes: Vec<Node>,
+}
+
+impl FileSystem {
+    fn new() -> Self {
+        FileSystem {
+            nodes: vec![Node {
+                name: String::new(),
+                parent: ROOT,
+                kind: EntryKind::Directory,
+                size: 0,
+                children: Vec::new(),
+            }],
         }
     }
-}
 
-fn process_ls_line(entry: &str) -> Entry {
-    let (sizedir, name) = entry.split_once(' ').unwrap();
-    if sizedir == "dir" {
-        Entry::Directory(Directory {
+    fn child(&self, node: usize, name: &str) -> Option<usize> {
+        self.nodes[node]
+            .children
+            .iter()
+            .copied()
+            .find(|&child| self.nodes[child].name == name)
+    }
+
+    /// Adds an entry to the directory, unless it's already there from an earlier `ls`
+    fn add(&mut self, parent: usize, name: &str, kind: EntryKind, size: usize) -> usize {
+        if let Some(existing) = self.child(parent, name) {
+            return existing;
+        }
+
+        let id = self.nodes.len();
+        self.nodes.push(Node {
             name: name.to_owned(),
+            parent,
+            kind,
+            size,
             children: Vec::new(),
-        })
-    } else {
-        Entry::File(File {
-            size: sizedir.parse().unwrap(),
-        })
+        });
+        self.nodes[parent].children.push(id);
+
+        // Keep every ancestor's cached size up to date
+        let mut current = parent;
+        loop {
+            self.nodes[current].size += size;
+            if current == ROOT {
+                break;
+            }
+            current = self.nodes[current].parent;
+        }
+
+        id
     }
-}
 
-fn process_commands<'a, L>(lines: &mut L, current: &mut Directory)
-where
-    L: Iterator<Item = &'a str>,
-{
-    let mut reading_ls = false;
-    while let Some(line) = lines.next() {
-        if line.starts_with('$') {
-            let mut parts = line.split(' ').skip(1);
-            match (parts.next(), parts.next()) {
-                (Some("ls"), None) => {
-                    reading_ls = true;
+    /// Follows an absolute path, or one relative to `from`, treating `..` at the root as the root
+    fn resolve_from(&self, from: usize, path: &str) -> Option<usize> {
+        let start = if path.starts_with('/') { ROOT } else { from };
+
+        path.split('/')
+            .filter(|part| !part.is_empty() && *part != ".")
+            .try_fold(start, |current, part| {
+                if part == ".." {
+                    Some(self.nodes[current].parent)
+                } else {
+                    self.child(current, part)
                 }
-                (Some("cd"), Some("..")) => {
-                    break;
+            })
+    }
+
+    pub fn resolve(&self, path: &str) -> Option<usize> {
+        self.resolve_from(ROOT, path)
+    }
+
+    pub fn path_of(&self, node: usize) -> String {
+        if node == ROOT {
+            return "/".to_string();
+        }
+
+        let mut parts = Vec::new();
+        let mut current = node;
+        while current != ROOT {
+            parts.push(self.nodes[current].name.as_str());
+            current = self.nodes[current].parent;
+        }
+        parts.reverse();
+
+        format!("/{}", parts.join("/"))
+    }
+
+    /// Size of the file, or total size of everything in the directory, at `path`
+    pub fn size_of(&self, path: &str) -> Option<usize> {
+        self.resolve(path).map(|node| self.nodes[node].size)
+    }
+
+    /// The entries in the directory at `path`, in the order they were first listed
+    pub fn list(&self, path: &str) -> Option<Vec<Listing>> {
+        let node = self.resolve(path)?;
+        if self.nodes[node].kind != EntryKind::Directory {
+            return None;
+        }
+
+        Some(
+            self.nodes[node]
+                .children
+                .iter()
+                .map(|&child| Listing {
+                    name: self.nodes[child].name.clone(),
+                    kind: self.nodes[child].kind,
+                    size: self.nodes[child].size,
+                })
+                .collect(),
+        )
+    }
+
+    /// Every directory's size, including the root
+    pub fn directory_sizes(&self) -> impl Iterator<Item = usize> + '_ {
+        self.nodes
+            .iter()
+            .filter(|node| node.kind == EntryKind::Directory)
+            .map(|node| node.size)
+    }
+
+    /// Sum of the sizes of every directory no bigger than `limit`, counting nested files once
+    /// for each directory they're under
+    pub fn total_of_directories_up_to(&self, limit: usize) -> usize {
+        self.directory_sizes().filter(|&size| size <= limit).sum()
+    }
+
+    /// Size of the smallest directory that frees enough space on `disk` when deleted, or `None`
+    /// if there's already enough free space
+    pub fn directory_to_delete(&self, disk: Disk) -> Result<Option<usize>, CleanupError> {
+        let used = self.nodes[ROOT].size;
+        if used > disk.capacity {
+            return Err(CleanupError::OverCapacity {
+                used,
+                capacity: disk.capacity,
+            });
+        }
+        if disk.required > disk.capacity {
+            return Err(CleanupError::UpdateTooLarge {
+                required: disk.required,
+                capacity: disk.capacity,
+            });
+        }
+
+        let free = disk.capacity - used;
+        if free >= disk.required {
+            return Ok(None);
+        }
+        let needed = disk.required - free;
+
+        // The root always qualifies, since the update fits on an empty disk
+        Ok(self.directory_sizes().filter(|&size| size >= needed).min())
+    }
+
+    /// Children of a directory, largest first, with ties broken by name
+    fn sorted_children(&self, node: usize) -> Vec<usize> {
+        let mut children = self.nodes[node].children.clone();
+        children.sort_by(|&a, &b| {
+            self.nodes[b]
+                .size
+                .cmp(&self.nodes[a].size)
+                .then_with(|| self.nodes[a].name.cmp(&self.nodes[b].name))
+        });
+        children
+    }
+
+    /// Renders the filesystem like `tree`, with exact sizes and each directory's entries sorted
+    /// largest first. Entries deeper than `max_depth` below the root are left out.
+    pub fn render_tree(&self, max_depth: Option<usize>) -> String {
+        let mut output = format!("/ ({})\n", self.nodes[ROOT].size);
+        self.render_tree_children(ROOT, "", 1, max_depth, &mut output);
+        output
+    }
+
+    fn render_tree_children(
+        &self,
+        node: usize,
+        prefix: &str,
+        depth: usize,
+        max_depth: Option<usize>,
+        output: &mut String,
+    ) {
+        if max_depth.is_some_and(|max| depth > max) {
+            return;
+        }
+
+        let children = self.sorted_children(node);
+        for (index, &child) in children.iter().enumerate() {
+            let last = index == children.len() - 1;
+            let entry = &self.nodes[child];
+            let branch = if last { "└── " } else { "├── " };
+            let suffix = if entry.kind == EntryKind::Directory {
+                "/"
+            } else {
+                ""
+            };
+            output.push_str(&format!(
+                "{}{}{}{} ({})\n",
+                prefix, branch, entry.name, suffix, entry.size
+            ));
+
+            if entry.kind == EntryKind::Directory {
+                let nested = format!("{}{}", prefix, if last { "    " } else { "│   " });
+                self.render_tree_children(child, &nested, depth + 1, max_depth, output);
+            }
+        }
+    }
+
+    /// Renders directory totals like `du -h | sort -rh`, only including directories at most
+    /// `max_depth` below the root (as with `du --max-depth`)
+    pub fn render_du(&self, max_depth: Option<usize>) -> String {
+        let mut directories = Vec::new();
+        let mut stack = vec![(ROOT, 0)];
+        while let Some((node, depth)) = stack.pop() {
+            directories.push(node);
+            if max_depth.is_some_and(|max| depth >= max) {
+                continue;
+            }
+            for &child in &self.nodes[node].children {
+                if self.nodes[child].kind == EntryKind::Directory {
+                    stack.push((child, depth + 1));
                 }
-                (Some("cd"), Some(directory)) => {
-                    for entry in current.children.iter_mut() {
-                        if let Entry::Directory(dir) = entry {
-                            if dir.name == directory {
-                                process_commands(lines, dir);
-                                break;
+            }
+        }
+
+        directories.sort_by_cached_key(|&node| {
+            (std::cmp::Reverse(self.nodes[node].size), self.path_of(node))
+        });
+
+        directories
+            .into_iter()
+            .map(|node| {
+                format!(
+                    "{}\t{}\n",
+                    human_size(self.nodes[node].size),
+                    self.path_of(node)
+                )
+            })
+            .collect()
+    }
+
+    pub fn from_transcript(input: &str) -> Result<Self, TranscriptError> {
+        let mut fs = FileSystem::new();
+        let mut current = ROOT;
+        let mut reading_ls = false;
+
+        for (index, line) in input.lines().enumerate() {
+            let line_number = index + 1;
+
+            if let Some(command) = line.strip_prefix("$ ") {
+                reading_ls = false;
+                let mut parts = command.split(' ');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some("ls"), None, None) => {
+                        reading_ls = true;
+                    }
+                    (Some("cd"), Some(path), None) => {
+                        current = fs.change_directory(current, path).ok_or_else(|| {
+                            TranscriptError::NotADirectory {
+                                line: line_number,
+                                path: path.to_owned(),
                             }
-                        }
+                        })?;
                     }
+                    _ => return Err(TranscriptError::UnknownCommand { line: line_number }),
+                }
+            } else {
+                let unexpected = TranscriptError::UnexpectedOutput { line: line_number };
+                if !reading_ls {
+                    return Err(unexpected);
+                }
+
+                // Entry from 'ls'
+                let (size_or_dir, name) = line.split_once(' ').ok_or(unexpected.clone())?;
+                if size_or_dir == "dir" {
+                    fs.add(current, name, EntryKind::Directory, 0);
+                } else {
+                    let size = size_or_dir.parse().map_err(|_| unexpected)?;
+                    fs.add(current, name, EntryKind::File, size);
                 }
-                _ => panic!("Invalid command!"),
-            }
-        } else {
-            if !reading_ls {
-                panic!("Found entry without ls");
             }
-            // Entry from 'ls'
-            current.children.push(process_ls_line(line));
         }
+
+        Ok(fs)
     }
-}
 
-fn keep_only_small(size: usize) -> usize {
-    if size <= 100_000 {
-        size
-    } else {
-        0
+    /// Moves to `path`, creating any directories along the way that haven't been listed yet
+    fn change_directory(&mut self, from: usize, path: &str) -> Option<usize> {
+        let start = if path.starts_with('/') { ROOT } else { from };
+
+        path.split('/')
+            .filter(|part| !part.is_empty() && *part != ".")
+            .try_fold(start, |current, part| {
+                let next = if part == ".." {
+                    self.nodes[current].parent
+                } else {
+                    self.add(current, part, EntryKind::Directory, 0)
+                };
+
+                (self.nodes[next].kind == EntryKind::Directory).then_some(next)
+            })
     }
 }
 
-fn sum_small_dirs(current: &Directory) -> usize {
-    current
-        .children
-        .iter()
-        .filter_map(|entry| match entry {
-            Entry::Directory(dir) => Some(sum_small_dirs(dir)),
-            Entry::File(_) => None,
-        })
-        .sum::<usize>()
-        + keep_only_small(current.size())
-}
+/// Formats a size the way `du -h` does: powers of 1024, rounded up, with one decimal place below 10
+fn human_size(size: usize) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
 
-fn find_possible_directories(current: &Directory, target: usize, possibilities: &mut Vec<usize>) {
-    let size = current.size();
-    if size > target {
-        possibilities.push(size);
+    if size < 1024 {
+        return size.to_string();
+    }
 
-        // Only recurse if the current directory is at least large enough
-        for entry in &current.children {
-            if let Entry::Directory(dir) = entry {
-                find_possible_directories(dir, target, possibilities);
-            }
-        }
+    let mut value = size as f64;
+    let mut unit = 0;
+    value /= 1024.0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    let tenths = (value * 10.0).ceil() / 10.0;
+    if tenths < 10.0 {
+        format!("{:.1}{}", tenths, UNITS[unit])
+    } else {
+        format!("{}{}", value.ceil(), UNITS[unit])
     }
 }
 
 #[aoc(day7, part1)]
 fn solve_part1(input: &str) -> usize {
-    let mut root = Directory {
-        name: "/".into(),
-        children: Vec::new(),
-    };
-    let mut lines = input.lines();
-    process_commands(&mut lines, &mut root);
-
-    sum_small_dirs(&root)
+    let fs = FileSystem::from_transcript(input).unwrap();
+
+    fs.total_of_directories_up_to(SMALL_DIRECTORY_LIMIT)
 }
 
 #[aoc(day7, part2)]
 fn solve_part2(input: &str) -> usize {
-    let mut root = Directory {
-        name: "/".into(),
-        children: Vec::new(),
-    };
-    let mut lines = input.lines();
-    process_commands(&mut lines, &mut root);
-
-    let unused_space = 70_000_000 - root.size();
-    let target = 30_000_000 - unused_space;
-
-    let mut possibilities = Vec::new();
-    find_possible_directories(&root, target, &mut possibilities);
-    possibilities.sort();
-    possibilities[0]
+    let fs = FileSystem::from_transcript(input).unwrap();
+
+    fs.directory_to_delete(DEVICE).unwrap().unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "$ cd /
+$ ls
+dir a
+14848514 b.txt
+8504156 c.dat
+dir d
+$ cd a
+$ ls
+dir e
+29116 f
+2557 g
+62596 h.lst
+$ cd e
+$ ls
+584 i
+$ cd /d
+$ ls
+4060174 j
+8033020 d.log
+5626152 d.ext
+7214296 k
+$ cd ../a/e
+$ ls
+584 i";
+
+    #[test]
+    fn test_transcript_paths() {
+        let fs = FileSystem::from_transcript(EXAMPLE).unwrap();
+
+        assert_eq!(fs.size_of("/a/e"), Some(584));
+        assert_eq!(fs.size_of("/a"), Some(94853));
+        assert_eq!(fs.size_of("/d/j"), Some(4060174));
+        assert_eq!(fs.size_of("/"), Some(48381165));
+        assert_eq!(fs.size_of("/missing"), None);
+        assert_eq!(fs.path_of(fs.resolve("/a/e/../../d").unwrap()), "/d");
+        assert_eq!(
+            fs.list("/a/e"),
+            Some(vec![Listing {
+                name: "i".to_string(),
+                kind: EntryKind::File,
+                size: 584
+            }])
+        );
+
+        assert_eq!(
+            FileSystem::from_transcript("$ cd /\n$ rm -rf a").err(),
+            Some(TranscriptError::UnknownCommand { line: 2 })
+        );
+    }
+
+    #[test]
+    fn test_reports() {
+        let fs = FileSystem::from_transcript(
+            "$ cd /
+$ ls
+dir a
+2048 b
+$ cd a
+$ ls
+dir c
+100 d
+$ cd c
+$ ls
+5000 e",
+        )
+        .unwrap();
+
+        assert_eq!(
+            fs.render_tree(None),
+            "/ (7148)
+├── a/ (5100)
+│   ├── c/ (5000)
+│   │   └── e (5000)
+│   └── d (100)
+└── b (2048)
+"
+        );
+        assert_eq!(
+            fs.render_tree(Some(1)),
+            "/ (7148)\n├── a/ (5100)\n└── b (2048)\n"
+        );
+        assert_eq!(fs.render_du(None), "7.0K\t/\n5.0K\t/a\n4.9K\t/a/c\n");
+        assert_eq!(fs.render_du(Some(1)), "7.0K\t/\n5.0K\t/a\n");
+    }
+
+    #[test]
+    fn test_cleanup_queries() {
+        let fs = FileSystem::from_transcript(EXAMPLE).unwrap();
+
+        assert_eq!(fs.total_of_directories_up_to(100_000), 95437);
+        assert_eq!(fs.directory_to_delete(DEVICE), Ok(Some(24933642)));
+        assert_eq!(
+            fs.directory_to_delete(Disk {
+                capacity: 50_000_000,
+                required: 1_000_000
+            }),
+            Ok(None)
+        );
+        assert_eq!(
+            fs.directory_to_delete(Disk {
+                capacity: 50_000_000,
+                required: 60_000_000
+            }),
+            Err(CleanupError::UpdateTooLarge {
+                required: 60_000_000,
+                capacity: 50_000_000
+            })
+        );
+        assert_eq!(
+            fs.directory_to_delete(Disk {
+                capacity: 40_000_000,
+                required: 1_000_000
+            }),
+            Err(CleanupError::OverCapacity {
+                used: 48381165,
+                capacity: 40_000_000
+            })
+        );
+    }
 }
@@ -1,15 +1,14 @@
 use aoc_runner_derive::aoc;
 use std::collections::HashSet;
-use std::iter::repeat;
 
-#[derive(Clone, Copy, Hash, PartialEq, Eq)]
-struct Point {
-    x: isize,
-    y: isize,
+#[derive(Clone, Copy, Debug, Hash, PartialEq, Eq)]
+pub struct Point {
+    pub x: isize,
+    pub y: isize,
 }
 
 impl Point {
-    fn new(x: isize, y: isize) -> Self {
+    pub fn new(x: isize, y: isize) -> Self {
         Self { x, y }
     }
 
@@ -22,90 +21,221 @@ impl Point {
         }
     }
 
+    fn touches(self, other: Self) -> bool {
+        (other.x - self.x).abs() <= 1 && (other.y - self.y).abs() <= 1
+    }
+
+    /// One step towards `target` (diagonally if not in the same row or column), or nowhere if
+    /// the two are already touching
     fn move_closer(self, target: Self) -> Self {
-        let (diff_x, diff_y) = match (target.x - self.x, target.y - self.y) {
-            (2, 2) | (2, 1) | (1, 2) => (1, 1),
-            (2, -1) | (2, -2) | (1, -2) => (1, -1),
-            (-2, 2) | (-2, 1) | (-1, 2) => (-1, 1),
-            (-2, -1) | (-2, -2) | (-1, -2) => (-1, -1),
-            (2, 0) => (1, 0),
-            (-2, 0) => (-1, 0),
-            (0, 2) => (0, 1),
-            (0, -2) => (0, -1),
-            _ => (0, 0),
-        };
-
-        Point::new(self.x + diff_x, self.y + diff_y)
+        if self.touches(target) {
+            return self;
+        }
+
+        Point::new(
+            self.x + (target.x - self.x).signum(),
+            self.y + (target.y - self.y).signum(),
+        )
     }
 }
 
-#[derive(Clone, Copy)]
-enum Direction {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
     Up,
     Down,
     Left,
     Right,
 }
 
-struct Rope {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Motion {
+    pub direction: Direction,
+    pub distance: usize,
+}
+
+pub struct Rope {
     knots: Vec<Point>,
-    visited: HashSet<Point>,
+    visited: Vec<HashSet<Point>>,
 }
 
 impl Rope {
-    fn new(count: usize) -> Self {
+    pub fn new(count: usize) -> Self {
+        assert!(count > 0, "A rope needs at least one knot");
+
         let origin = Point::new(0, 0);
-        let knots = vec![origin; count];
         Self {
-            knots,
-            visited: HashSet::from([origin]),
+            knots: vec![origin; count],
+            visited: vec![HashSet::from([origin]); count],
         }
     }
 
-    fn move_head(&mut self, direction: Direction) {
-        self.knots[0] = self.knots[0].shift(direction);
+    pub fn knots(&self) -> &[Point] {
+        &self.knots
+    }
+
+    /// Every position the knot has been in, with 0 being the head
+    pub fn visited(&self, knot: usize) -> &HashSet<Point> {
+        &self.visited[knot]
+    }
+
+    pub fn tail_visited(&self) -> &HashSet<Point> {
+        self.visited.last().unwrap()
+    }
+
+    /// Moves the head one square, with the rest of the rope following
+    pub fn step_head(&mut self, direction: Direction) {
+        let head = self.knots[0].shift(direction);
+        self.jump_head(head);
+    }
+
+    /// Moves the head straight to `target`. Each following knot then walks one square at a time
+    /// until it touches the knot ahead of it, so long jumps leave a trail of visited squares.
+    pub fn jump_head(&mut self, target: Point) {
+        self.knots[0] = target;
+        self.visited[0].insert(target);
 
         for index in 1..self.knots.len() {
-            self.knots[index] = self.knots[index].move_closer(self.knots[index - 1]);
+            let leader = self.knots[index - 1];
+            while !self.knots[index].touches(leader) {
+                self.knots[index] = self.knots[index].move_closer(leader);
+                self.visited[index].insert(self.knots[index]);
+            }
+        }
+    }
+
+    /// Applies the motion one square at a time, as the puzzle does
+    pub fn apply(&mut self, motion: Motion) {
+        for _ in 0..motion.distance {
+            self.step_head(motion.direction);
         }
-        self.visited.insert(self.knots[self.knots.len() - 1]);
     }
 }
 
-fn parse_moves(input: &str) -> impl Iterator<Item = Direction> + '_ {
-    input.lines().flat_map(|line| {
-        let (dir_str, count_str) = line.split_once(' ').unwrap();
-        let count: usize = count_str.parse().unwrap();
-        let dir = match dir_str {
-            "U" => Direction::Up,
-            "D" => Direction::Down,
-            "L" => Direction::Left,
-            "R" => Direction::Right,
-            _ => panic!("Invalid input"),
-        };
-
-        repeat(dir).take(count)
-    })
+/// Draws the rope the way the puzzle does, with the head as `H`, following knots numbered from 1
+/// (or `T` for a two-knot rope), the start as `s` and squares in `trail` as `#`. Knots nearer
+/// the head are drawn over later ones. `bounds` gives the inclusive bottom-left and top-right
+/// corners, with y increasing upwards.
+pub fn render_frame(knots: &[Point], trail: &HashSet<Point>, bounds: (Point, Point)) -> String {
+    let (min, max) = bounds;
+    let mut output = String::new();
+
+    for y in (min.y..=max.y).rev() {
+        for x in min.x..=max.x {
+            let point = Point::new(x, y);
+            let chr = match knots.iter().position(|&knot| knot == point) {
+                Some(0) => 'H',
+                Some(_) if knots.len() == 2 => 'T',
+                Some(index) => std::char::from_digit((index % 36) as u32, 36).unwrap(),
+                None if point == Point::new(0, 0) => 's',
+                None if trail.contains(&point) => '#',
+                None => '.',
+            };
+            output.push(chr);
+        }
+        output.push('\n');
+    }
+
+    output
 }
 
-#[aoc(day9, part1)]
-fn solve_part1(input: &str) -> usize {
-    let mut rope = Rope::new(2);
+/// Renders the rope after every single-square step of the motions (plus the starting position),
+/// all sharing one frame size big enough to hold the whole animation. The trail is the squares
+/// the tail has visited so far.
+pub fn animate(motions: &[Motion], knots: usize) -> Vec<String> {
+    let mut rope = Rope::new(knots);
+    let mut snapshots = vec![(rope.knots.clone(), rope.tail_visited().clone())];
+
+    for motion in motions {
+        for _ in 0..motion.distance {
+            rope.step_head(motion.direction);
+            snapshots.push((rope.knots.clone(), rope.tail_visited().clone()));
+        }
+    }
+
+    let origin = Point::new(0, 0);
+    let (min, max) = rope
+        .visited
+        .iter()
+        .flatten()
+        .fold((origin, origin), |(min, max), point| {
+            (
+                Point::new(min.x.min(point.x), min.y.min(point.y)),
+                Point::new(max.x.max(point.x), max.y.max(point.y)),
+            )
+        });
+
+    snapshots
+        .iter()
+        .map(|(knots, trail)| render_frame(knots, trail, (min, max)))
+        .collect()
+}
 
-    for dir in parse_moves(input) {
-        rope.move_head(dir);
+pub fn parse_motions(input: &str) -> Vec<Motion> {
+    input
+        .lines()
+        .map(|line| {
+            let (dir_str, count_str) = line.split_once(' ').unwrap();
+            let direction = match dir_str {
+                "U" => Direction::Up,
+                "D" => Direction::Down,
+                "L" => Direction::Left,
+                "R" => Direction::Right,
+                _ => panic!("Invalid input"),
+            };
+
+            Motion {
+                direction,
+                distance: count_str.parse().unwrap(),
+            }
+        })
+        .collect()
+}
+
+fn tail_positions(input: &str, knots: usize) -> usize {
+    let mut rope = Rope::new(knots);
+
+    for motion in parse_motions(input) {
+        rope.apply(motion);
     }
 
-    rope.visited.len()
+    rope.tail_visited().len()
+}
+
+#[aoc(day9, part1)]
+fn solve_part1(input: &str) -> usize {
+    tail_positions(input, 2)
 }
 
 #[aoc(day9, part2)]
 fn solve_part2(input: &str) -> usize {
-    let mut rope = Rope::new(10);
+    tail_positions(input, 10)
+}
 
-    for dir in parse_moves(input) {
-        rope.move_head(dir);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rope() {
+        let example = "R 4\nU 4\nL 3\nD 1\nR 4\nD 1\nL 5\nR 2";
+        assert_eq!(tail_positions(example, 2), 13);
+        assert_eq!(tail_positions(example, 10), 1);
+        assert_eq!(
+            tail_positions("R 5\nU 8\nL 8\nD 3\nR 17\nD 10\nL 25\nU 20", 10),
+            36
+        );
+
+        let mut rope = Rope::new(3);
+        rope.jump_head(Point::new(4, 2));
+        assert_eq!(
+            rope.knots(),
+            &[Point::new(4, 2), Point::new(3, 2), Point::new(2, 2)]
+        );
+        assert_eq!(rope.visited(1).len(), 4);
+        assert_eq!(rope.tail_visited().len(), 3);
+
+        let frames = animate(&parse_motions("R 3"), 2);
+        assert_eq!(frames.len(), 4);
+        assert_eq!(frames[3], "s#TH\n");
     }
-
-    rope.visited.len()
 }
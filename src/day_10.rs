@@ -1,96 +1,466 @@
 use aoc_runner_derive::aoc;
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
 
-enum Instruction {
+/// Register names, in the order they're stored
+const REGISTERS: [char; 4] = ['w', 'x', 'y', 'z'];
+
+const GLYPH_WIDTH: usize = 4;
+const GLYPH_HEIGHT: usize = 6;
+
+/// The letters the CRT is known to draw, each as six rows of four pixels
+const GLYPHS: [(char, &str); 16] = [
+    ('A', ".##.#..##..######..##..#"),
+    ('B', "###.#..####.#..##..####."),
+    ('C', ".##.#..##...#...#..#.##."),
+    ('E', "#####...###.#...#...####"),
+    ('F', "#####...###.#...#...#..."),
+    ('G', ".##.#..##...#.###..#.###"),
+    ('H', "#..##..######..##..##..#"),
+    ('J', "..##...#...#...##..#.##."),
+    ('K', "#..##.#.##..#.#.#.#.#..#"),
+    ('L', "#...#...#...#...#...####"),
+    ('O', ".##.#..##..##..##..#.##."),
+    ('P', "###.#..##..####.#...#..."),
+    ('R', "###.#..##..####.#.#.#..#"),
+    ('S', ".####...#....##....####."),
+    ('U', "#..##..##..##..##..#.##."),
+    ('Z', "####...#..#..#..#...####"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
     Noop,
-    Addx(isize),
+    Add,
+    Set,
 }
 
-struct Communicator<F> {
-    register: isize,
-    cycle: isize,
-    cycle_callback: F,
+/// Every opcode with its mnemonic, the number of cycles it takes and whether it is followed by a
+/// register name (as in `addx`) and an operand
+const OPCODES: [(Opcode, &str, usize, bool); 3] = [
+    (Opcode::Noop, "noop", 1, false),
+    (Opcode::Add, "add", 2, true),
+    (Opcode::Set, "set", 1, true),
+];
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    UnknownOpcode { line: usize, opcode: String },
+    InvalidOperand { line: usize, operand: String },
 }
 
-impl<F> Communicator<F>
-where
-    F: FnMut(isize, isize),
-{
-    fn new(mut cycle_callback: F) -> Self {
-        cycle_callback(1, 1);
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OcrError {
+    /// The screen isn't six pixels tall
+    BadHeight(usize),
+    /// The glyph at this position (counting from 0) doesn't match any known letter
+    UnknownGlyph { index: usize, pixels: String },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Instruction {
+    opcode: Opcode,
+    register: usize,
+    operand: isize,
+}
 
-        Self {
-            register: 1,
+impl Instruction {
+    fn cycles(self) -> usize {
+        OPCODES
+            .iter()
+            .find(|(opcode, ..)| *opcode == self.opcode)
+            .map(|(_, _, cycles, _)| *cycles)
+            .unwrap()
+    }
+}
+
+/// Size of the CRT, and how many pixels wide the sprite centred on `x` is
+#[derive(Clone, Copy, Debug)]
+pub struct Display {
+    pub width: usize,
+    pub height: usize,
+    pub sprite_width: usize,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display {
+            width: 40,
+            height: 6,
+            sprite_width: 3,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Render {
+    /// `#` for lit pixels and `.` for dark ones
+    Ascii,
+    /// Unicode half blocks, fitting two rows of pixels on each line
+    Blocks,
+    /// A plain (P1) portable bitmap
+    Pbm,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stop {
+    Halted,
+    /// About to start the instruction at this index
+    Breakpoint(usize),
+}
+
+pub struct Cpu {
+    program: Vec<Instruction>,
+    /// Index of the instruction currently being executed
+    pc: usize,
+    /// Cycles already spent on the current instruction
+    progress: usize,
+    cycle: usize,
+    registers: [isize; 4],
+    breakpoints: HashSet<usize>,
+    trace: Vec<(usize, [isize; 4])>,
+}
+
+impl Cpu {
+    pub fn new(program: Vec<Instruction>) -> Self {
+        let mut registers = [0; 4];
+        registers[1] = 1;
+
+        Cpu {
+            program,
+            pc: 0,
+            progress: 0,
             cycle: 1,
-            cycle_callback,
+            registers,
+            breakpoints: HashSet::new(),
+            trace: Vec::new(),
         }
     }
 
-    fn execute_instruction(&mut self, instruction: Instruction) {
-        match instruction {
-            Instruction::Noop => {
-                self.cycle += 1;
+    pub fn register(&self, name: char) -> Option<isize> {
+        let index = REGISTERS.iter().position(|&register| register == name)?;
+        Some(self.registers[index])
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+
+    /// Every cycle run so far, with the register values during that cycle
+    pub fn trace(&self) -> &[(usize, [isize; 4])] {
+        &self.trace
+    }
+
+    pub fn add_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.insert(pc);
+    }
+
+    pub fn remove_breakpoint(&mut self, pc: usize) {
+        self.breakpoints.remove(&pc);
+    }
+
+    /// Runs a single cycle, returning its number and the register values during it. Instructions
+    /// only update the registers once their last cycle is over.
+    pub fn step(&mut self) -> Option<(usize, [isize; 4])> {
+        let instruction = *self.program.get(self.pc)?;
+        let during = (self.cycle, self.registers);
+        self.trace.push(during);
+
+        self.cycle += 1;
+        self.progress += 1;
+        if self.progress == instruction.cycles() {
+            match instruction.opcode {
+                Opcode::Noop => {}
+                Opcode::Add => self.registers[instruction.register] += instruction.operand,
+                Opcode::Set => self.registers[instruction.register] = instruction.operand,
             }
-            Instruction::Addx(delta) => {
-                self.cycle += 1;
-                (self.cycle_callback)(self.cycle, self.register);
-                self.cycle += 1;
-                self.register += delta;
+
+            self.pc += 1;
+            self.progress = 0;
+        }
+
+        Some(during)
+    }
+
+    /// Runs at least one cycle, then continues until the program ends or is about to start an
+    /// instruction with a breakpoint on it
+    pub fn run(&mut self) -> Stop {
+        while self.step().is_some() {
+            if self.progress == 0 && self.breakpoints.contains(&self.pc) {
+                return Stop::Breakpoint(self.pc);
             }
         }
 
-        (self.cycle_callback)(self.cycle, self.register);
+        Stop::Halted
     }
 }
 
-fn parse_instructions(input: &str) -> Vec<Instruction> {
-    input
-        .lines()
-        .map(|line| match line {
-            "noop" => Instruction::Noop,
-            _ => {
-                let delta = line.split(' ').nth(1).unwrap().parse().unwrap();
-                Instruction::Addx(delta)
+fn parse_instruction(line_number: usize, line: &str) -> Result<Instruction, ParseError> {
+    let mut parts = line.split(' ');
+    let mnemonic = parts.next().unwrap_or_default();
+    let unknown = || ParseError::UnknownOpcode {
+        line: line_number,
+        opcode: mnemonic.to_string(),
+    };
+
+    let (opcode, register) = OPCODES
+        .iter()
+        .find_map(|&(opcode, name, _, takes_register)| {
+            if !takes_register {
+                return (mnemonic == name).then_some((opcode, 0));
             }
+
+            let mut suffix = mnemonic.strip_prefix(name)?.chars();
+            let register = suffix.next()?;
+            if suffix.next().is_some() {
+                return None;
+            }
+            let index = REGISTERS.iter().position(|&name| name == register)?;
+            Some((opcode, index))
         })
+        .ok_or_else(unknown)?;
+
+    let operand = if opcode == Opcode::Noop {
+        0
+    } else {
+        let operand = parts.next().unwrap_or_default();
+        operand.parse().map_err(|_| ParseError::InvalidOperand {
+            line: line_number,
+            operand: operand.to_string(),
+        })?
+    };
+
+    Ok(Instruction {
+        opcode,
+        register,
+        operand,
+    })
+}
+
+pub fn parse_instructions(input: &str) -> Result<Vec<Instruction>, ParseError> {
+    input
+        .lines()
+        .enumerate()
+        .map(|(index, line)| parse_instruction(index + 1, line))
         .collect()
 }
 
 #[aoc(day10, part1)]
 fn solve_part1(input: &str) -> isize {
+    let mut cpu = Cpu::new(parse_instructions(input).unwrap());
     let mut signal_strength = 0;
-    let mut comm = Communicator::new(|cycle, register| {
+
+    while let Some((cycle, registers)) = cpu.step() {
         if cycle % 40 == 20 {
-            signal_strength += cycle * register;
+            signal_strength += cycle as isize * registers[1];
         }
-    });
-
-    for instruction in parse_instructions(input) {
-        comm.execute_instruction(instruction);
     }
 
     signal_strength
 }
 
-#[aoc(day10, part2)]
-fn solve_part2(input: &str) -> String {
-    let mut screen = String::with_capacity(247);
-    screen.push('\n'); // So the output is aligned
-    let mut comm = Communicator::new(|cycle, register| {
-        let x_position = (cycle - 1) % 40;
-        if (register - x_position).abs() <= 1 {
-            screen.push('#');
-        } else {
-            screen.push(' ');
-        }
+/// Draws the screen one row at a time. Pixels the program doesn't reach stay dark.
+pub fn draw_screen(program: Vec<Instruction>, display: Display) -> Vec<Vec<bool>> {
+    let mut cpu = Cpu::new(program);
+    let mut screen = vec![vec![false; display.width]; display.height];
+    // The sprite covers `x` plus as many pixels to either side as fit, leaning right if even
+    let left_reach = (display.sprite_width.saturating_sub(1) / 2) as isize;
+    let right_reach = (display.sprite_width / 2) as isize;
 
-        if x_position == 39 {
-            screen.push('\n');
+    while let Some((cycle, registers)) = cpu.step() {
+        let pixel = cycle - 1;
+        if pixel >= display.width * display.height {
+            break;
         }
-    });
 
-    for instruction in parse_instructions(input) {
-        comm.execute_instruction(instruction);
+        let (row, col) = (pixel / display.width, pixel % display.width);
+        let offset = col as isize - registers[1];
+        screen[row][col] =
+            display.sprite_width > 0 && -left_reach <= offset && offset <= right_reach;
     }
 
     screen
 }
+
+pub fn render(screen: &[Vec<bool>], format: Render) -> String {
+    let mut output = String::new();
+
+    match format {
+        Render::Ascii => {
+            for row in screen {
+                output.extend(row.iter().map(|&lit| if lit { '#' } else { '.' }));
+                output.push('\n');
+            }
+        }
+        Render::Blocks => {
+            for rows in screen.chunks(2) {
+                let empty = vec![false; rows[0].len()];
+                let lower = rows.get(1).unwrap_or(&empty);
+                output.extend(rows[0].iter().zip(lower).map(|pair| match pair {
+                    (true, true) => '█',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (false, false) => ' ',
+                }));
+                output.push('\n');
+            }
+        }
+        Render::Pbm => {
+            let width = screen.first().map_or(0, |row| row.len());
+            writeln!(output, "P1\n{} {}", width, screen.len()).unwrap();
+            for row in screen {
+                let pixels: Vec<_> = row.iter().map(|&lit| if lit { "1" } else { "0" }).collect();
+                writeln!(output, "{}", pixels.join(" ")).unwrap();
+            }
+        }
+    }
+
+    output
+}
+
+pub fn save_pbm(screen: &[Vec<bool>], path: impl AsRef<Path>) -> io::Result<()> {
+    std::fs::write(path, render(screen, Render::Pbm))
+}
+
+/// Reads the letters drawn on the screen, which are four pixels wide with a one pixel gap
+pub fn read_letters(screen: &[Vec<bool>]) -> Result<String, OcrError> {
+    if screen.len() != GLYPH_HEIGHT {
+        return Err(OcrError::BadHeight(screen.len()));
+    }
+
+    let width = screen.iter().map(|row| row.len()).min().unwrap_or(0);
+    let count = (width + 1) / (GLYPH_WIDTH + 1);
+
+    (0..count)
+        .map(|index| {
+            let left = index * (GLYPH_WIDTH + 1);
+            let pixels: String = screen
+                .iter()
+                .flat_map(|row| &row[left..left + GLYPH_WIDTH])
+                .map(|&lit| if lit { '#' } else { '.' })
+                .collect();
+
+            GLYPHS
+                .iter()
+                .find(|(_, glyph)| *glyph == pixels)
+                .map(|(letter, _)| *letter)
+                .ok_or(OcrError::UnknownGlyph { index, pixels })
+        })
+        .collect()
+}
+
+/// Returns the letters on the screen, or the screen itself if some of them can't be read
+#[aoc(day10, part2)]
+fn solve_part2(input: &str) -> String {
+    let pixels = draw_screen(parse_instructions(input).unwrap(), Display::default());
+
+    // Leading newline so the output is aligned
+    read_letters(&pixels).unwrap_or_else(|_| format!("\n{}", render(&pixels, Render::Ascii)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cpu() {
+        let program = parse_instructions("noop\naddx 3\naddx -5\nsety 7").unwrap();
+        let mut cpu = Cpu::new(program);
+        cpu.add_breakpoint(2);
+
+        assert_eq!(cpu.run(), Stop::Breakpoint(2));
+        assert_eq!(cpu.register('x'), Some(4));
+        assert_eq!(cpu.step(), Some((4, [0, 4, 0, 0])));
+        assert_eq!(cpu.run(), Stop::Halted);
+        assert_eq!(cpu.register('x'), Some(-1));
+        assert_eq!(cpu.register('y'), Some(7));
+
+        let xs: Vec<_> = cpu
+            .trace()
+            .iter()
+            .map(|(_, registers)| registers[1])
+            .collect();
+        assert_eq!(xs, vec![1, 1, 1, 4, 4, -1]);
+
+        assert_eq!(
+            parse_instructions("noop\njmp 4"),
+            Err(ParseError::UnknownOpcode {
+                line: 2,
+                opcode: "jmp".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_read_letters() {
+        let screen: Vec<Vec<bool>> = [
+            "#..#.####.###..",
+            "#..#.#....#..#.",
+            "####.###..#..#.",
+            "#..#.#....###..",
+            "#..#.#....#.#..",
+            "#..#.####.#..#.",
+        ]
+        .iter()
+        .map(|row| row.chars().map(|chr| chr == '#').collect())
+        .collect();
+
+        assert_eq!(read_letters(&screen), Ok("HER".to_string()));
+
+        let mut smudged = screen;
+        smudged[0][5] = false;
+        assert_eq!(
+            read_letters(&smudged),
+            Err(OcrError::UnknownGlyph {
+                index: 1,
+                pixels: ".####...###.#...#...####".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_display_geometry_and_rendering() {
+        // An even sprite leans right, covering x and x + 1
+        let program = parse_instructions(&format!("addx 2{}", "\nnoop".repeat(8))).unwrap();
+        let display = Display {
+            width: 5,
+            height: 2,
+            sprite_width: 2,
+        };
+        let screen = draw_screen(program, display);
+
+        assert_eq!(render(&screen, Render::Ascii), ".#.##\n...##\n");
+        assert_eq!(render(&screen, Render::Blocks), " ▀ ██\n");
+        assert_eq!(
+            render(&screen, Render::Pbm),
+            "P1\n5 2\n0 1 0 1 1\n0 0 0 1 1\n"
+        );
+
+        let path = std::env::temp_dir().join("day_10_test_display.pbm");
+        save_pbm(&screen, &path).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            render(&screen, Render::Pbm)
+        );
+        std::fs::remove_file(path).unwrap();
+
+        // A program that ends early leaves the rest of the screen dark, and an odd number of
+        // rows leaves the bottom half of the last block line empty
+        let program = parse_instructions(&"noop\n".repeat(10)).unwrap();
+        let display = Display {
+            width: 4,
+            height: 3,
+            sprite_width: 4,
+        };
+        let screen = draw_screen(program, display);
+
+        assert_eq!(render(&screen, Render::Ascii), "####\n####\n##..\n");
+        assert_eq!(render(&screen, Render::Blocks), "████\n▀▀  \n");
+    }
+}
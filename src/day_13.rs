@@ -1,122 +1,296 @@
 use aoc_runner_derive::aoc;
 use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
 
-#[derive(Clone)]
-enum Signal {
-    Int(u32),
-    List(Vec<Signal>),
+/// How deeply lists may nest when parsing with `FromStr`
+pub const DEFAULT_MAX_DEPTH: usize = 256;
+
+/// A distress signal packet. Integers are `u64` by default, but any ordered integer type works.
+///
+/// Equality follows the packet ordering rather than structure, so `1`, `[1]` and `[[1]]` are all
+/// equal to each other.
+#[derive(Clone, Debug)]
+pub enum Packet<T = u64> {
+    Int(T),
+    List(Vec<Packet<T>>),
 }
 
-impl PartialEq for Signal {
-    fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (Signal::Int(left), Signal::Int(right)) => left.eq(right),
-            (Signal::List(left), Signal::List(right)) => left.eq(right),
-            (left @ Signal::List(_), Signal::Int(right)) => {
-                left.eq(&Signal::List(vec![Signal::Int(*right)]))
-            }
-            (Signal::Int(left), right @ Signal::List(_)) => {
-                Signal::List(vec![Signal::Int(*left)]).eq(right)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Syntax,
+    IntegerOverflow,
+    TooDeep { max_depth: usize },
+}
+
+impl<T: FromStr> Packet<T> {
+    pub fn parse_with_depth(input: &str, max_depth: usize) -> Result<Self, ParseError> {
+        parser::parse_packet(input, max_depth)
+    }
+}
+
+impl<T: FromStr> FromStr for Packet<T> {
+    type Err = ParseError;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse_with_depth(input, DEFAULT_MAX_DEPTH)
+    }
+}
+
+impl<T: fmt::Display> fmt::Display for Packet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Int(value) => write!(f, "{}", value),
+            Packet::List(items) => {
+                write!(f, "[")?;
+                for (index, item) in items.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
             }
         }
     }
 }
 
-impl Eq for Signal {}
+/// Compares an integer against a list as if the integer were wrapped in a one-item list
+fn cmp_int_list<T: Ord>(value: &T, list: &[Packet<T>]) -> Ordering {
+    match list.first() {
+        None => Ordering::Greater,
+        Some(first) => match cmp_int_packet(value, first) {
+            Ordering::Equal if list.len() > 1 => Ordering::Less,
+            ordering => ordering,
+        },
+    }
+}
+
+fn cmp_int_packet<T: Ord>(value: &T, packet: &Packet<T>) -> Ordering {
+    match packet {
+        Packet::Int(other) => value.cmp(other),
+        Packet::List(list) => cmp_int_list(value, list),
+    }
+}
 
-impl PartialOrd for Signal {
+impl<T: Ord> PartialEq for Packet<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T: Ord> Eq for Packet<T> {}
+
+impl<T: Ord> PartialOrd for Packet<T> {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(self.cmp(other))
     }
 }
 
-impl Ord for Signal {
+impl<T: Ord> Ord for Packet<T> {
     fn cmp(&self, other: &Self) -> Ordering {
         match (self, other) {
-            (Signal::Int(left), Signal::Int(right)) => left.cmp(right),
-            (Signal::List(left), Signal::List(right)) => left.cmp(right),
-            (left @ Signal::List(_), Signal::Int(right)) => {
-                left.cmp(&Signal::List(vec![Signal::Int(*right)]))
-            }
-            (Signal::Int(left), right @ Signal::List(_)) => {
-                Signal::List(vec![Signal::Int(*left)]).cmp(right)
-            }
+            (Packet::Int(left), Packet::Int(right)) => left.cmp(right),
+            (Packet::List(left), Packet::List(right)) => left.cmp(right),
+            (Packet::List(left), Packet::Int(right)) => cmp_int_list(right, left).reverse(),
+            (Packet::Int(left), Packet::List(right)) => cmp_int_list(left, right),
         }
     }
 }
 
-#[aoc(day13, part1)]
-fn solve_part1(input: &str) -> usize {
+/// The divider packets inserted by the distress signal protocol
+const DIVIDERS: [&str; 2] = ["[[2]]", "[[6]]"];
+
+/// Every packet in the input, ignoring the blank lines between pairs
+pub fn parse_packets(input: &str) -> Result<Vec<Packet>, ParseError> {
     input
-        .split("\n\n")
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// 1-based indices of the pairs whose left packet comes before the right one
+pub fn ordered_pairs<T: Ord>(packets: &[Packet<T>]) -> Vec<usize> {
+    packets
+        .chunks(2)
         .enumerate()
-        .filter_map(|(index, pair)| {
-            let mut lines = pair.lines();
-            let left = parser::parse_signal(lines.next().unwrap());
-            let right = parser::parse_signal(lines.next().unwrap());
-
-            if left < right {
-                Some(index + 1)
-            } else {
-                None
-            }
+        .filter(|(_, pair)| pair.len() == 2 && pair[0] < pair[1])
+        .map(|(index, _)| index + 1)
+        .collect()
+}
+
+/// 1-based position each divider would have if it were added to the packets and everything
+/// sorted. A divider goes ahead of any packets equal to it, and dividers that compare equal keep
+/// the order they're given in.
+pub fn divider_positions<T: Ord>(packets: &[Packet<T>], dividers: &[Packet<T>]) -> Vec<usize> {
+    dividers
+        .iter()
+        .enumerate()
+        .map(|(index, divider)| {
+            let packets_before = packets.iter().filter(|packet| *packet < divider).count();
+            let dividers_before = dividers
+                .iter()
+                .enumerate()
+                .filter(|&(other_index, other)| {
+                    other < divider || (other == divider && other_index < index)
+                })
+                .count();
+
+            packets_before + dividers_before + 1
         })
-        .sum()
+        .collect()
+}
+
+#[aoc(day13, part1)]
+fn solve_part1(input: &str) -> usize {
+    let packets = parse_packets(input).unwrap();
+
+    ordered_pairs(&packets).into_iter().sum()
 }
 
 #[aoc(day13, part2)]
 fn solve_part2(input: &str) -> usize {
-    let first_divider = parser::parse_signal("[[2]]");
-    let second_divider = parser::parse_signal("[[6]]");
+    let packets = parse_packets(input).unwrap();
+    let dividers = DIVIDERS
+        .iter()
+        .map(|divider| divider.parse().unwrap())
+        .collect::<Vec<Packet>>();
 
-    let mut signals = input
-        .lines()
-        .filter(|line| !line.is_empty())
-        .map(parser::parse_signal)
-        .collect::<Vec<_>>();
-    signals.push(first_divider.clone());
-    signals.push(second_divider.clone());
-    signals.sort();
-
-    signals
-        .into_iter()
-        .enumerate()
-        .fold(1, |acc, (index, signal)| {
-            if signal == first_divider || signal == second_divider {
-                acc * (index + 1)
-            } else {
-                acc
-            }
-        })
+    divider_positions(&packets, &dividers).into_iter().product()
 }
 
 mod parser {
-    use super::Signal;
+    use super::{Packet, ParseError};
+    use std::str::FromStr;
 
     use nom::branch::alt;
-    use nom::character::complete::{char, u32};
-    use nom::combinator::all_consuming;
+    use nom::character::complete::{char, digit1};
+    use nom::combinator::{all_consuming, map, map_res};
+    use nom::error::{Error, ErrorKind};
     use nom::multi::separated_list0;
     use nom::sequence::delimited;
-    use nom::IResult;
+    use nom::{Err, IResult};
+
+    fn integer<T: FromStr>(input: &str) -> IResult<&str, Packet<T>> {
+        // Only overflow can make a run of digits fail to parse, so that's a hard failure
+        map_res(digit1, str::parse)(input)
+            .map(|(rest, value)| (rest, Packet::Int(value)))
+            .map_err(|err: Err<Error<&str>>| match err {
+                Err::Error(error) if error.code == ErrorKind::MapRes => Err::Failure(error),
+                err => err,
+            })
+    }
+
+    fn list<T: FromStr>(input: &str, depth: usize, max_depth: usize) -> IResult<&str, Packet<T>> {
+        if depth >= max_depth && input.starts_with('[') {
+            return Err(Err::Failure(Error::new(input, ErrorKind::TooLarge)));
+        }
 
-    fn integer(input: &str) -> IResult<&str, Signal> {
-        let (rest, value) = u32(input)?;
-        Ok((rest, Signal::Int(value)))
+        map(
+            delimited(
+                char('['),
+                separated_list0(char(','), |input| packet(input, depth + 1, max_depth)),
+                char(']'),
+            ),
+            Packet::List,
+        )(input)
     }
 
-    fn list(input: &str) -> IResult<&str, Signal> {
-        let (rest, value) =
-            delimited(char('['), separated_list0(char(','), signal), char(']'))(input)?;
+    fn packet<T: FromStr>(input: &str, depth: usize, max_depth: usize) -> IResult<&str, Packet<T>> {
+        alt((integer, |input| list(input, depth, max_depth)))(input)
+    }
 
-        Ok((rest, Signal::List(value)))
+    pub(super) fn parse_packet<T: FromStr>(
+        input: &str,
+        max_depth: usize,
+    ) -> Result<Packet<T>, ParseError> {
+        match all_consuming(|input| packet(input, 0, max_depth))(input) {
+            Ok((_, packet)) => Ok(packet),
+            Err(Err::Failure(error)) if error.code == ErrorKind::TooLarge => {
+                Err(ParseError::TooDeep { max_depth })
+            }
+            Err(Err::Failure(error)) if error.code == ErrorKind::MapRes => {
+                Err(ParseError::IntegerOverflow)
+            }
+            Err(_) => Err(ParseError::Syntax),
+        }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn signal(input: &str) -> IResult<&str, Signal> {
-        alt((integer, list))(input)
+    #[test]
+    fn test_packet_round_trip_and_order() {
+        for text in [
+            "[]",
+            "[[]]",
+            "[1,[2,[3,[4,[5,6,7]]]],8,9]",
+            "18446744073709551615",
+        ] {
+            assert_eq!(text.parse::<Packet>().unwrap().to_string(), text);
+        }
+
+        let packet = |text: &str| text.parse::<Packet>().unwrap();
+        assert!(packet("[[1],[2,3,4]]") < packet("[[1],4]"));
+        assert!(packet("[9]") > packet("[[8,7,6]]"));
+        assert!(packet("[[4,4],4,4]") < packet("[[4,4],4,4,4]"));
+        assert!(packet("[7,7,7]") < packet("[[7],7,7,7]"));
+        assert!(packet("[]") < packet("[3]"));
+        assert!(packet("[[[]]]") > packet("[[]]"));
+        assert_eq!(packet("[[[1]]]"), packet("1"));
+
+        assert_eq!(
+            "18446744073709551616".parse::<Packet>(),
+            Err(ParseError::IntegerOverflow)
+        );
+        assert!("300".parse::<Packet<u128>>().is_ok());
+        assert_eq!(
+            Packet::<u64>::parse_with_depth("[[[1]]]", 2),
+            Err(ParseError::TooDeep { max_depth: 2 })
+        );
+        assert!(Packet::<u64>::parse_with_depth("[[1]]", 2).is_ok());
+        assert_eq!("[1,]".parse::<Packet>(), Err(ParseError::Syntax));
     }
 
-    pub(super) fn parse_signal(input: &str) -> Signal {
-        all_consuming(signal)(input).unwrap().1
+    #[test]
+    fn test_example_queries() {
+        let packets = parse_packets(
+            "[1,1,3,1,1]
+[1,1,5,1,1]
+
+[[1],[2,3,4]]
+[[1],4]
+
+[9]
+[[8,7,6]]
+
+[[4,4],4,4]
+[[4,4],4,4,4]
+
+[7,7,7,7]
+[7,7,7]
+
+[]
+[3]
+
+[[[]]]
+[[]]
+
+[1,[2,[3,[4,[5,6,7]]]],8,9]
+[1,[2,[3,[4,[5,6,0]]]],8,9]",
+        )
+        .unwrap();
+
+        assert_eq!(ordered_pairs(&packets), vec![1, 2, 4, 6]);
+
+        let dividers = ["[[2]]", "[[6]]", "[[2]]", "[]"]
+            .iter()
+            .map(|divider| divider.parse().unwrap())
+            .collect::<Vec<Packet>>();
+        assert_eq!(divider_positions(&packets, &dividers[..2]), vec![10, 14]);
+        assert_eq!(divider_positions(&packets, &dividers), vec![11, 16, 12, 1]);
     }
 }
@@ -1,8 +1,19 @@
 use aoc_runner_derive::aoc;
 use std::collections::hash_map::Entry;
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, VecDeque};
 use std::iter::Cycle;
 
+const STANDARD_SHAPES: [&str; 5] = [
+    "####",
+    ".#.\n###\n.#.",
+    "..#\n..#\n###",
+    "#\n#\n#\n#",
+    "##\n##",
+];
+
+/// Rows are only pruned once the chamber holds at least this many of them
+const MIN_PRUNE_ROWS: usize = 256;
+
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 struct Point {
     x: usize,
@@ -15,8 +26,8 @@ impl Point {
     }
 }
 
-#[derive(Clone, Copy)]
-enum Jet {
+#[derive(Clone, Copy, Debug)]
+pub enum Jet {
     Left,
     Right,
 }
@@ -31,105 +42,126 @@ impl Jet {
     }
 }
 
+/// A rock shape, stored as one bitmask per row from the bottom up, with bit 0 as the leftmost column
+#[derive(Clone, Debug)]
+pub struct Shape {
+    rows: Vec<u8>,
+    width: usize,
+}
+
+impl Shape {
+    /// Parses a shape from ASCII art, with `#` for rock and `.` for empty space.
+    /// The last line of the art is the bottom of the shape.
+    pub fn from_ascii(art: &str) -> Self {
+        let mut rows = Vec::new();
+        let mut width = 0;
+
+        for line in art.lines().rev() {
+            let mut row = 0u8;
+            for (x, chr) in line.chars().enumerate() {
+                match chr {
+                    '#' => {
+                        assert!(x < 8, "Shapes can be at most 8 columns wide");
+                        row |= 1 << x;
+                        width = width.max(x + 1);
+                    }
+                    '.' => {}
+                    _ => panic!("Invalid shape character"),
+                }
+            }
+            rows.push(row);
+        }
+
+        // Blank lines above or below the rock would leave it floating inside its own shape
+        assert!(width > 0, "Shapes must contain at least one rock");
+        let bottom = rows.iter().position(|&row| row != 0).unwrap();
+        let top = rows.iter().rposition(|&row| row != 0).unwrap();
+        rows.truncate(top + 1);
+        rows.drain(..bottom);
+
+        Shape { rows, width }
+    }
+}
+
+pub fn standard_shapes() -> Vec<Shape> {
+    STANDARD_SHAPES.into_iter().map(Shape::from_ascii).collect()
+}
+
+/// Dimensions of the chamber and where new rocks appear in it
 #[derive(Clone, Copy, Debug)]
-enum RockKind {
-    Horizontal,
-    Plus,
-    L,
-    Vertical,
-    Box,
+pub struct CaveConfig {
+    /// Number of columns in the chamber, at most 8
+    pub width: usize,
+    /// Distance of a new rock's left edge from the left wall
+    pub spawn_x: usize,
+    /// Distance of a new rock's bottom edge above the highest settled rock (or the floor)
+    pub spawn_y: usize,
+}
+
+impl Default for CaveConfig {
+    fn default() -> Self {
+        CaveConfig {
+            width: 7,
+            spawn_x: 2,
+            spawn_y: 4,
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
-struct Rock {
-    kind: RockKind,
+struct Rock<'a> {
+    shape: &'a Shape,
     origin: Point,
 }
 
-impl Rock {
-    fn new(kind: RockKind, origin: Point) -> Self {
-        Rock { kind, origin }
+impl<'a> Rock<'a> {
+    fn new(shape: &'a Shape, origin: Point) -> Self {
+        Rock { shape, origin }
     }
 
     fn move_left(self) -> Option<Self> {
         if self.origin.x > 0 {
             let origin = Point::new(self.origin.x - 1, self.origin.y);
-            Some(Rock::new(self.kind, origin))
+            Some(Rock::new(self.shape, origin))
         } else {
             None
         }
     }
 
-    fn move_right(self) -> Option<Self> {
-        let max_x = match self.kind {
-            RockKind::Horizontal => 3,
-            RockKind::Plus => 4,
-            RockKind::L => 4,
-            RockKind::Vertical => 6,
-            RockKind::Box => 5,
-        };
-
-        if self.origin.x < max_x {
+    fn move_right(self, cave_width: usize) -> Option<Self> {
+        if self.origin.x + self.shape.width < cave_width {
             let origin = Point::new(self.origin.x + 1, self.origin.y);
-            Some(Rock::new(self.kind, origin))
+            Some(Rock::new(self.shape, origin))
         } else {
             None
         }
     }
 
-    fn move_down(self) -> Option<Self> {
-        if self.origin.y > 1 {
-            let origin = Point::new(self.origin.x, self.origin.y - 1);
-            Some(Rock::new(self.kind, origin))
-        } else {
-            None
-        }
+    fn move_down(self) -> Self {
+        let origin = Point::new(self.origin.x, self.origin.y - 1);
+        Rock::new(self.shape, origin)
     }
 
-    fn points(self) -> impl Iterator<Item = Point> {
-        let x = self.origin.x;
-        let y = self.origin.y;
-        match self.kind {
-            RockKind::Horizontal => vec![
-                Point::new(x, y),
-                Point::new(x + 1, y),
-                Point::new(x + 2, y),
-                Point::new(x + 3, y),
-            ],
-            RockKind::Plus => vec![
-                Point::new(x + 1, y),
-                Point::new(x, y + 1),
-                Point::new(x + 1, y + 1),
-                Point::new(x + 1, y + 2),
-                Point::new(x + 2, y + 1),
-            ],
-            RockKind::L => vec![
-                Point::new(x, y),
-                Point::new(x + 1, y),
-                Point::new(x + 2, y),
-                Point::new(x + 2, y + 1),
-                Point::new(x + 2, y + 2),
-            ],
-            RockKind::Vertical => vec![
-                Point::new(x, y),
-                Point::new(x, y + 1),
-                Point::new(x, y + 2),
-                Point::new(x, y + 3),
-            ],
-            RockKind::Box => vec![
-                Point::new(x, y),
-                Point::new(x, y + 1),
-                Point::new(x + 1, y),
-                Point::new(x + 1, y + 1),
-            ],
-        }
-        .into_iter()
+    /// The (y, mask) pairs for each row of the rock in its current position
+    fn rows(self) -> impl Iterator<Item = (usize, u8)> + 'a {
+        let Point { x, y } = self.origin;
+        self.shape
+            .rows
+            .iter()
+            .enumerate()
+            .map(move |(offset, row)| (y + offset, row << x))
     }
 }
 
 struct Cave<J> {
     jets: J,
-    blocked: HashSet<Point>,
+    config: CaveConfig,
+    full: u8,
+    /// Settled rock, one bitmask per row starting at `base`. Row 0 is the floor.
+    rows: VecDeque<u8>,
+    /// Height of the first row in `rows`; everything below it is treated as solid
+    base: usize,
+    prune_at: usize,
     max_y: usize,
 }
 
@@ -137,23 +169,42 @@ impl<I> Cave<Cycle<I>>
 where
     I: Iterator<Item = Jet> + Clone,
 {
-    fn new(jets: I) -> Self {
+    fn new(jets: I, config: CaveConfig) -> Self {
+        assert!(
+            config.width <= 8,
+            "The chamber can be at most 8 columns wide"
+        );
+        let full = ((1u16 << config.width) - 1) as u8;
+
         Cave {
             jets: jets.cycle(),
-            blocked: HashSet::new(),
+            config,
+            full,
+            rows: VecDeque::from([full]),
+            base: 0,
+            prune_at: MIN_PRUNE_ROWS,
             max_y: 0,
         }
     }
 
+    fn row(&self, y: usize) -> u8 {
+        if y < self.base {
+            self.full
+        } else {
+            self.rows.get(y - self.base).copied().unwrap_or(0)
+        }
+    }
+
     fn is_covered(&self, rock: Rock) -> bool {
-        rock.points().any(|point| self.blocked.contains(&point))
+        rock.rows().any(|(y, mask)| self.row(y) & mask != 0)
     }
 
     fn hash_top(&self) -> String {
-        let mut hash = String::with_capacity(280);
-        for y in (self.max_y - 39)..=self.max_y {
-            for x in 0..7 {
-                if self.blocked.contains(&Point::new(x, y)) {
+        let mut hash = String::with_capacity(40 * self.config.width);
+        for y in self.max_y.saturating_sub(39)..=self.max_y {
+            let row = self.row(y);
+            for x in 0..self.config.width {
+                if row & (1 << x) != 0 {
                     hash.push('#');
                 } else {
                     hash.push('.');
@@ -164,19 +215,68 @@ where
         hash
     }
 
-    fn moved_rock(&self, maybe_rock: Option<Rock>) -> Option<Rock> {
+    fn moved_rock<'a>(&self, maybe_rock: Option<Rock<'a>>) -> Option<Rock<'a>> {
         maybe_rock.and_then(|rock| (!self.is_covered(rock)).then_some(rock))
     }
 
-    fn drop_rock(&mut self, kind: RockKind) {
-        let start = Point::new(2, self.max_y + 4);
-        let mut rock = Rock::new(kind, start);
+    fn settle(&mut self, rock: Rock) {
+        for (y, mask) in rock.rows() {
+            if mask == 0 {
+                continue;
+            }
+
+            let index = y - self.base;
+            if index >= self.rows.len() {
+                self.rows.resize(index + 1, 0);
+            }
+            self.rows[index] |= mask;
+            self.max_y = self.max_y.max(y);
+        }
+
+        if self.rows.len() >= self.prune_at {
+            self.prune();
+            self.prune_at = MIN_PRUNE_ROWS.max(self.rows.len() * 2);
+        }
+    }
+
+    /// Drops every row that a falling rock can no longer reach, found by flooding down from the top
+    fn prune(&mut self) {
+        let mut reachable = self.full;
+        let mut lowest = self.max_y + 1;
+
+        for y in (self.base..=self.max_y).rev() {
+            let open = !self.row(y) & self.full;
+            reachable &= open;
+
+            // Spread sideways through open cells until nothing changes
+            loop {
+                let spread = (reachable | reachable << 1 | reachable >> 1) & open;
+                if spread == reachable {
+                    break;
+                }
+                reachable = spread;
+            }
+
+            if reachable == 0 {
+                break;
+            }
+            lowest = y;
+        }
+
+        let drop = lowest - self.base;
+        self.rows.drain(..drop);
+        self.base += drop;
+    }
+
+    fn drop_rock(&mut self, shape: &Shape) {
+        let start = Point::new(self.config.spawn_x, self.max_y + self.config.spawn_y);
+        let mut rock = Rock::new(shape, start);
 
         loop {
             // Do horizontal movement
             let updated_rock = match self.jets.next().unwrap() {
                 Jet::Left => rock.move_left(),
-                Jet::Right => rock.move_right(),
+                Jet::Right => rock.move_right(self.config.width),
             };
 
             if let Some(moved) = self.moved_rock(updated_rock) {
@@ -184,16 +284,13 @@ where
             }
 
             // Do vertical movement
-            match self.moved_rock(rock.move_down()) {
+            match self.moved_rock(Some(rock.move_down())) {
                 Some(moved) => {
                     rock = moved;
                 }
                 None => {
                     // We've found a spot to stop
-                    for point in rock.points() {
-                        self.blocked.insert(point);
-                        self.max_y = self.max_y.max(point.y);
-                    }
+                    self.settle(rock);
                     break;
                 }
             }
@@ -201,45 +298,52 @@ where
     }
 }
 
-fn parse_jets(input: &str) -> Vec<Jet> {
-    input.chars().map(Jet::from_char).collect()
+/// Panics if a shape couldn't fit in the chamber where it spawns, or would spawn inside the tower
+fn check_shapes(shapes: &[Shape], config: CaveConfig) {
+    assert!(
+        config.spawn_y >= 1,
+        "Rocks must spawn at least one row above the tower"
+    );
+
+    for shape in shapes {
+        assert!(
+            config.spawn_x + shape.width <= config.width,
+            "A shape {} columns wide can't spawn at x = {} in a chamber {} columns wide",
+            shape.width,
+            config.spawn_x,
+            config.width
+        );
+    }
 }
 
-fn rocks() -> impl Iterator<Item = RockKind> {
-    [
-        RockKind::Horizontal,
-        RockKind::Plus,
-        RockKind::L,
-        RockKind::Vertical,
-        RockKind::Box,
-    ]
-    .into_iter()
-    .cycle()
+pub fn parse_jets(input: &str) -> Vec<Jet> {
+    input.chars().map(Jet::from_char).collect()
 }
 
-#[aoc(day17, part1)]
-fn solve_part1(input: &str) -> usize {
-    let jets = parse_jets(input);
-    let mut cave = Cave::new(jets.into_iter());
+/// Height of the tower after `count` rocks have fallen, simulating every single rock
+pub fn simulate_height(jets: &[Jet], shapes: &[Shape], config: CaveConfig, count: usize) -> usize {
+    check_shapes(shapes, config);
+    let mut cave = Cave::new(jets.iter().copied(), config);
 
-    for rock in rocks().take(2022) {
-        cave.drop_rock(rock);
+    for shape in shapes.iter().cycle().take(count) {
+        cave.drop_rock(shape);
     }
 
     cave.max_y
 }
 
-#[aoc(day17, part2)]
-fn solve_part2(input: &str) -> usize {
-    let jets = parse_jets(input);
-    let sweep = jets.len() * 5;
+/// Height of the tower after `count` rocks have fallen, dropping `shapes` in order and skipping
+/// ahead once the top of the tower starts repeating
+pub fn tower_height(jets: &[Jet], shapes: &[Shape], config: CaveConfig, count: usize) -> usize {
+    check_shapes(shapes, config);
+    let sweep = jets.len() * shapes.len();
     let mut cyclic_part = 0;
     let mut last: Option<usize> = None;
-    let mut cave = Cave::new(jets.into_iter());
+    let mut cave = Cave::new(jets.iter().copied(), config);
     let mut heights: HashMap<String, (usize, usize)> = HashMap::new();
 
-    for (index, rock) in rocks().enumerate() {
-        cave.drop_rock(rock);
+    for (index, shape) in shapes.iter().cycle().take(count).enumerate() {
+        cave.drop_rock(shape);
         if (index + 1) % sweep == 0 && last.is_none() {
             let hash = cave.hash_top();
 
@@ -252,7 +356,7 @@ fn solve_part2(input: &str) -> usize {
 
                     let period = index + 1 - old_index;
                     let height_diff = cave.max_y - old_height;
-                    let remaining = 1_000_000_000_000 - index - 1;
+                    let remaining = count - index - 1;
                     cyclic_part = (remaining / period) * height_diff;
                     last = Some((remaining % period) + index);
                 }
@@ -266,3 +370,116 @@ fn solve_part2(input: &str) -> usize {
 
     cyclic_part + cave.max_y
 }
+
+#[aoc(day17, part1)]
+fn solve_part1(input: &str) -> usize {
+    let jets = parse_jets(input);
+    simulate_height(&jets, &standard_shapes(), CaveConfig::default(), 2022)
+}
+
+#[aoc(day17, part2)]
+fn solve_part2(input: &str) -> usize {
+    let jets = parse_jets(input);
+    tower_height(
+        &jets,
+        &standard_shapes(),
+        CaveConfig::default(),
+        1_000_000_000_000,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_heights() {
+        let jets = parse_jets(">>><<><>><<<>><>>><<<>>><<<><<<>><>><<>>");
+        let shapes = standard_shapes();
+        let config = CaveConfig::default();
+
+        assert_eq!(simulate_height(&jets, &shapes, config, 2022), 3068);
+        // Tall enough that the cave gets pruned many times over
+        assert_eq!(simulate_height(&jets, &shapes, config, 50_000), 75720);
+        let mut cave = Cave::new(jets.iter().copied(), config);
+        for shape in shapes.iter().cycle().take(50_000) {
+            cave.drop_rock(shape);
+        }
+        assert!(cave.base > 70_000);
+        assert_eq!(
+            tower_height(&jets, &shapes, config, 1_000_000_000_000),
+            1514285714288
+        );
+    }
+
+    #[test]
+    fn test_cycle_detection_custom_setup() {
+        let jets = parse_jets(">><<><>><<<>><>>><<<>>><<<><<<>><>><<>>");
+        let shapes = vec![
+            Shape::from_ascii("###"),
+            Shape::from_ascii("#.\n##"),
+            Shape::from_ascii("#\n#"),
+        ];
+        let config = CaveConfig {
+            width: 5,
+            spawn_x: 1,
+            spawn_y: 3,
+        };
+
+        let count = 5000;
+        assert_eq!(
+            tower_height(&jets, &shapes, config, count),
+            simulate_height(&jets, &shapes, config, count)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "can't spawn")]
+    fn test_shape_wider_than_spawn_gap() {
+        let config = CaveConfig {
+            width: 5,
+            spawn_x: 3,
+            spawn_y: 3,
+        };
+
+        simulate_height(&parse_jets(">"), &standard_shapes(), config, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one row above")]
+    fn test_spawn_inside_tower() {
+        let config = CaveConfig {
+            spawn_y: 0,
+            ..CaveConfig::default()
+        };
+
+        simulate_height(&parse_jets(">"), &standard_shapes(), config, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one rock")]
+    fn test_empty_shape() {
+        Shape::from_ascii("..\n..");
+    }
+
+    #[test]
+    fn test_blank_shape_rows_are_trimmed() {
+        let jets = parse_jets(">");
+        let padded = [Shape::from_ascii("..\n#.\n#.\n..")];
+        let plain = [Shape::from_ascii("#\n#")];
+
+        assert_eq!(
+            simulate_height(&jets, &padded, CaveConfig::default(), 10),
+            simulate_height(&jets, &plain, CaveConfig::default(), 10)
+        );
+        assert_eq!(
+            simulate_height(
+                &jets,
+                &[Shape::from_ascii("#\n.")],
+                CaveConfig::default(),
+                3
+            ),
+            3
+        );
+    }
+}
@@ -1,12 +1,14 @@
 use aoc_runner_derive::aoc;
+use indexmap::IndexSet;
 use nom::{bytes::complete::tag, character::complete::i64, sequence::tuple, IResult};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::fmt::Write;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-struct Point {
-    x: isize,
-    y: isize,
-    z: isize,
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Point {
+    pub x: isize,
+    pub y: isize,
+    pub z: isize,
 }
 
 impl Point {
@@ -14,6 +16,11 @@ impl Point {
         Point { x, y, z }
     }
 
+    fn coords(self) -> [isize; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    /// The six face neighbors, ordered -x, +x, -y, +y, -z, +z
     fn adjacents(self) -> impl Iterator<Item = Point> {
         [
             Point::new(self.x - 1, self.y, self.z),
@@ -27,18 +34,203 @@ impl Point {
     }
 }
 
-struct LavaDroplet {
+/// A connected region of air fully enclosed by lava
+#[derive(Clone, Copy, Debug)]
+pub struct Pocket {
+    pub volume: usize,
+    pub min: Point,
+    pub max: Point,
+}
+
+/// A set of points packed one bit per voxel over a fixed box, starting at `min`
+#[derive(Clone)]
+struct VoxelGrid {
+    min: Point,
+    size: [usize; 3],
+    bits: Vec<u64>,
+}
+
+impl VoxelGrid {
+    fn new(min: Point, max: Point) -> Self {
+        let size = [
+            (max.x - min.x + 1).max(0) as usize,
+            (max.y - min.y + 1).max(0) as usize,
+            (max.z - min.z + 1).max(0) as usize,
+        ];
+        let voxels = size[0] * size[1] * size[2];
+
+        VoxelGrid {
+            min,
+            size,
+            bits: vec![0; voxels.div_ceil(64)],
+        }
+    }
+
+    /// An empty grid covering the same box
+    fn empty_like(&self) -> Self {
+        VoxelGrid {
+            min: self.min,
+            size: self.size,
+            bits: vec![0; self.bits.len()],
+        }
+    }
+
+    fn index(&self, point: Point) -> Option<usize> {
+        let x = usize::try_from(point.x - self.min.x).ok()?;
+        let y = usize::try_from(point.y - self.min.y).ok()?;
+        let z = usize::try_from(point.z - self.min.z).ok()?;
+
+        (x < self.size[0] && y < self.size[1] && z < self.size[2])
+            .then(|| (x * self.size[1] + y) * self.size[2] + z)
+    }
+
+    fn point(&self, index: usize) -> Point {
+        let z = index % self.size[2];
+        let y = (index / self.size[2]) % self.size[1];
+        let x = index / (self.size[2] * self.size[1]);
+
+        Point::new(
+            self.min.x + x as isize,
+            self.min.y + y as isize,
+            self.min.z + z as isize,
+        )
+    }
+
+    fn contains(&self, point: Point) -> bool {
+        self.index(point)
+            .map(|index| self.bits[index / 64] & (1 << (index % 64)) != 0)
+            .unwrap_or(false)
+    }
+
+    /// Adds the point, returning whether it was newly added. Panics if it lies outside the box.
+    fn insert(&mut self, point: Point) -> bool {
+        let index = self.index(point).expect("Point outside of voxel grid");
+        let mask = 1 << (index % 64);
+        let added = self.bits[index / 64] & mask == 0;
+        self.bits[index / 64] |= mask;
+
+        added
+    }
+
+    fn iter(&self) -> impl Iterator<Item = Point> + '_ {
+        self.bits
+            .iter()
+            .enumerate()
+            .flat_map(move |(word_index, &word)| {
+                let mut remaining = word;
+                std::iter::from_fn(move || {
+                    (remaining != 0).then(|| {
+                        let bit = remaining.trailing_zeros() as usize;
+                        remaining &= remaining - 1;
+                        self.point(word_index * 64 + bit)
+                    })
+                })
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum MeshFormat {
+    /// Wavefront OBJ, with shared vertices and one quad per face
+    Obj,
+    /// ASCII STL, with each face split into two triangles
+    Stl,
+}
+
+/// An axis-aligned rectangle on the surface of the droplet, with corners wound counter-clockwise
+/// when seen from outside
+#[derive(Clone, Copy, Debug)]
+struct Quad {
+    corners: [[isize; 3]; 4],
+    normal: [isize; 3],
+}
+
+impl Quad {
+    /// Builds the face pointing along `axis` (0 = x, 1 = y, 2 = z) in the plane `depth`, covering
+    /// `u_min..u_max` and `v_min..v_max` along the two remaining axes in cyclic order
+    fn new(
+        axis: usize,
+        positive: bool,
+        depth: isize,
+        u: (isize, isize),
+        v: (isize, isize),
+    ) -> Self {
+        let corner = |u, v| {
+            let mut corner = [0; 3];
+            corner[axis] = depth;
+            corner[(axis + 1) % 3] = u;
+            corner[(axis + 2) % 3] = v;
+            corner
+        };
+
+        let mut corners = [
+            corner(u.0, v.0),
+            corner(u.1, v.0),
+            corner(u.1, v.1),
+            corner(u.0, v.1),
+        ];
+        if !positive {
+            corners.reverse();
+        }
+
+        let mut normal = [0; 3];
+        normal[axis] = if positive { 1 } else { -1 };
+
+        Quad { corners, normal }
+    }
+}
+
+/// Marks every point in `visited`'s box that is connected to `start` through unvisited points
+/// that are `passable`, returning the size and extent of the region that was filled
+fn flood_fill<F>(visited: &mut VoxelGrid, start: Point, passable: F) -> Pocket
+where
+    F: Fn(Point) -> bool,
+{
+    let mut pocket = Pocket {
+        volume: 0,
+        min: start,
+        max: start,
+    };
+    let mut queue = VecDeque::new();
+    visited.insert(start);
+    queue.push_back(start);
+
+    while let Some(point) = queue.pop_front() {
+        pocket.volume += 1;
+        pocket.min = Point::new(
+            pocket.min.x.min(point.x),
+            pocket.min.y.min(point.y),
+            pocket.min.z.min(point.z),
+        );
+        pocket.max = Point::new(
+            pocket.max.x.max(point.x),
+            pocket.max.y.max(point.y),
+            pocket.max.z.max(point.z),
+        );
+
+        for neighbor in point.adjacents() {
+            if visited.index(neighbor).is_some() && passable(neighbor) && visited.insert(neighbor) {
+                queue.push_back(neighbor);
+            }
+        }
+    }
+
+    pocket
+}
+
+pub struct LavaDroplet {
     min: Point,
     max: Point,
-    cubes: HashSet<Point>,
+    /// Covers the bounding box padded by one on every side, so the outside can be flooded
+    cubes: VoxelGrid,
 }
 
 impl LavaDroplet {
-    fn surface_area(&self) -> usize {
+    pub fn surface_area(&self) -> usize {
         let mut area = 0;
         for point in self.cubes.iter() {
             for neighbor in point.adjacents() {
-                if !self.cubes.contains(&neighbor) {
+                if !self.cubes.contains(neighbor) {
                     area += 1;
                 }
             }
@@ -47,44 +239,184 @@ impl LavaDroplet {
         area
     }
 
-    fn outside_blocks(&self) -> HashSet<Point> {
-        let mut outside = HashSet::new();
-        let mut queue = VecDeque::new();
-        let (min_x, min_y, min_z) = (self.min.x - 1, self.min.y - 1, self.min.z - 1);
-        let (max_x, max_y, max_z) = (self.max.x + 1, self.max.y + 1, self.max.z + 1);
-        queue.push_back(Point::new(min_x, min_y, min_z));
+    fn outside_blocks(&self) -> VoxelGrid {
+        let mut outside = self.cubes.empty_like();
+        flood_fill(&mut outside, self.cubes.min, |neighbor| {
+            !self.cubes.contains(neighbor)
+        });
+
+        outside
+    }
+
+    /// Every pocket of air that can't be reached from outside the droplet
+    pub fn air_pockets(&self) -> Vec<Pocket> {
+        // Starting from the outside air means only enclosed air is left to fill
+        let mut visited = self.outside_blocks();
+        let mut pockets = Vec::new();
+
+        for x in self.min.x..=self.max.x {
+            for y in self.min.y..=self.max.y {
+                for z in self.min.z..=self.max.z {
+                    let point = Point::new(x, y, z);
+                    if self.cubes.contains(point) || visited.contains(point) {
+                        continue;
+                    }
+
+                    pockets.push(flood_fill(&mut visited, point, |neighbor| {
+                        !self.cubes.contains(neighbor)
+                    }));
+                }
+            }
+        }
+
+        pockets
+    }
+
+    /// Number of separate pieces of lava, where cubes sharing a face belong to the same piece
+    pub fn lava_components(&self) -> usize {
+        let mut visited = self.cubes.empty_like();
+        let mut components = 0;
 
-        while let Some(point) = queue.pop_front() {
-            if outside.contains(&point) {
+        for cube in self.cubes.iter() {
+            if visited.contains(cube) {
                 continue;
             }
 
-            outside.insert(point);
+            flood_fill(&mut visited, cube, |neighbor| self.cubes.contains(neighbor));
+            components += 1;
+        }
 
-            for neighbor in point.adjacents() {
-                if !self.cubes.contains(&neighbor)
-                    && neighbor.x >= min_x
-                    && neighbor.y >= min_y
-                    && neighbor.z >= min_z
-                    && neighbor.x <= max_x
-                    && neighbor.y <= max_y
-                    && neighbor.z <= max_z
-                {
-                    queue.push_back(neighbor);
+        components
+    }
+
+    /// One quad per exterior face, or with `merge` set, faces greedily merged into larger
+    /// rectangles (first along rows, then across them)
+    fn exterior_quads(&self, merge: bool) -> Vec<Quad> {
+        let outside = self.outside_blocks();
+
+        // Exposed faces grouped by direction and plane, as cells in that plane
+        let mut planes: BTreeMap<(usize, bool, isize), BTreeSet<(isize, isize)>> = BTreeMap::new();
+        for cube in self.cubes.iter() {
+            let coords = cube.coords();
+            for (direction, neighbor) in cube.adjacents().enumerate() {
+                if !outside.contains(neighbor) {
+                    continue;
                 }
+
+                let axis = direction / 2;
+                let positive = direction % 2 == 1;
+                let depth = coords[axis] + positive as isize;
+                let cell = (coords[(axis + 1) % 3], coords[(axis + 2) % 3]);
+                planes
+                    .entry((axis, positive, depth))
+                    .or_default()
+                    .insert(cell);
             }
         }
 
-        outside
+        let mut quads = Vec::new();
+        for ((axis, positive, depth), mut cells) in planes {
+            if !merge {
+                quads.extend(
+                    cells
+                        .into_iter()
+                        .map(|(u, v)| Quad::new(axis, positive, depth, (u, u + 1), (v, v + 1))),
+                );
+                continue;
+            }
+
+            // Greedily grow rectangles, first along u and then along v
+            let mut ordered: Vec<_> = cells.iter().copied().collect();
+            ordered.sort_by_key(|&(u, v)| (v, u));
+            for (u, v) in ordered {
+                if !cells.contains(&(u, v)) {
+                    continue;
+                }
+
+                let mut u_end = u + 1;
+                while cells.contains(&(u_end, v)) {
+                    u_end += 1;
+                }
+
+                let mut v_end = v + 1;
+                while (u..u_end).all(|u| cells.contains(&(u, v_end))) {
+                    v_end += 1;
+                }
+
+                for u in u..u_end {
+                    for v in v..v_end {
+                        cells.remove(&(u, v));
+                    }
+                }
+
+                quads.push(Quad::new(axis, positive, depth, (u, u_end), (v, v_end)));
+            }
+        }
+
+        quads
     }
 
-    fn exterior_surface_area(&self) -> usize {
+    /// Renders the exterior surface as a mesh file, optionally merging coplanar faces
+    pub fn export_mesh(&self, format: MeshFormat, merge: bool) -> String {
+        let quads = self.exterior_quads(merge);
+        let mut mesh = String::new();
+
+        match format {
+            MeshFormat::Obj => {
+                let mut vertices = IndexSet::new();
+                let mut normals = IndexSet::new();
+                let mut faces = Vec::new();
+
+                for quad in quads.iter() {
+                    let (normal, _) = normals.insert_full(quad.normal);
+                    let corners = quad.corners.map(|corner| vertices.insert_full(corner).0);
+                    faces.push((corners, normal));
+                }
+
+                for [x, y, z] in vertices.iter() {
+                    writeln!(mesh, "v {} {} {}", x, y, z).unwrap();
+                }
+                for [x, y, z] in normals.iter() {
+                    writeln!(mesh, "vn {} {} {}", x, y, z).unwrap();
+                }
+                // OBJ indices start at 1
+                for (corners, normal) in faces {
+                    write!(mesh, "f").unwrap();
+                    for corner in corners {
+                        write!(mesh, " {}//{}", corner + 1, normal + 1).unwrap();
+                    }
+                    writeln!(mesh).unwrap();
+                }
+            }
+            MeshFormat::Stl => {
+                writeln!(mesh, "solid droplet").unwrap();
+                for quad in quads.iter() {
+                    let [a, b, c, d] = quad.corners;
+                    for triangle in [[a, b, c], [a, c, d]] {
+                        let [nx, ny, nz] = quad.normal;
+                        writeln!(mesh, "  facet normal {} {} {}", nx, ny, nz).unwrap();
+                        writeln!(mesh, "    outer loop").unwrap();
+                        for [x, y, z] in triangle {
+                            writeln!(mesh, "      vertex {} {} {}", x, y, z).unwrap();
+                        }
+                        writeln!(mesh, "    endloop").unwrap();
+                        writeln!(mesh, "  endfacet").unwrap();
+                    }
+                }
+                writeln!(mesh, "endsolid droplet").unwrap();
+            }
+        }
+
+        mesh
+    }
+
+    pub fn exterior_surface_area(&self) -> usize {
         let mut area = 0;
         let outside = self.outside_blocks();
 
         for point in self.cubes.iter() {
             for neighbor in point.adjacents() {
-                if outside.contains(&neighbor) {
+                if outside.contains(neighbor) {
                     area += 1;
                 }
             }
@@ -106,19 +438,25 @@ fn parse_point(input: &str) -> Point {
     Point { x, y, z }
 }
 
-fn parse_droplet(input: &str) -> LavaDroplet {
-    let mut min = Point::new(100, 100, 100);
-    let mut max = Point::new(-100, -100, -100);
-    let mut cubes = HashSet::new();
+pub fn parse_droplet(input: &str) -> LavaDroplet {
+    let points: Vec<Point> = input.lines().map(parse_point).collect();
+    let mut min = points.first().copied().unwrap_or(Point::new(0, 0, 0));
+    let mut max = points.first().copied().unwrap_or(Point::new(-1, -1, -1));
 
-    for cube in input.lines().map(parse_point) {
+    for cube in points.iter() {
         min.x = min.x.min(cube.x);
         min.y = min.y.min(cube.y);
         min.z = min.z.min(cube.z);
         max.x = max.x.max(cube.x);
         max.y = max.y.max(cube.y);
         max.z = max.z.max(cube.z);
+    }
 
+    let mut cubes = VoxelGrid::new(
+        Point::new(min.x - 1, min.y - 1, min.z - 1),
+        Point::new(max.x + 1, max.y + 1, max.z + 1),
+    );
+    for cube in points {
         cubes.insert(cube);
     }
 
@@ -136,3 +474,70 @@ fn solve_part2(input: &str) -> usize {
     let droplet = parse_droplet(input);
     droplet.exterior_surface_area()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_air_pockets() {
+        let droplet = parse_droplet(
+            "2,2,2
+1,2,2
+3,2,2
+2,1,2
+2,3,2
+2,2,1
+2,2,3
+2,2,4
+2,2,6
+1,2,5
+3,2,5
+2,1,5
+2,3,5",
+        );
+
+        let pockets = droplet.air_pockets();
+        assert_eq!(pockets.len(), 1);
+        assert_eq!(pockets[0].volume, 1);
+        assert_eq!(pockets[0].min, Point::new(2, 2, 5));
+        assert_eq!(pockets[0].max, Point::new(2, 2, 5));
+        // The four cubes around the pocket only touch each other along edges
+        assert_eq!(droplet.lava_components(), 6);
+    }
+
+    #[test]
+    fn test_merged_mesh() {
+        let droplet = parse_droplet("1,1,1\n2,1,1\n1,2,1\n2,2,1");
+
+        let faces = |mesh: String| mesh.lines().filter(|line| line.starts_with("f ")).count();
+        assert_eq!(faces(droplet.export_mesh(MeshFormat::Obj, false)), 16);
+        assert_eq!(faces(droplet.export_mesh(MeshFormat::Obj, true)), 6);
+    }
+
+    #[test]
+    fn test_negative_coordinates() {
+        // A hollow 3x3x3 shell centred on (-5, -5, -5), plus a separate cube
+        let mut input = String::new();
+        for x in -6..=-4 {
+            for y in -6..=-4 {
+                for z in -6..=-4 {
+                    if (x, y, z) != (-5, -5, -5) {
+                        input.push_str(&format!("{},{},{}\n", x, y, z));
+                    }
+                }
+            }
+        }
+        input.push_str("-10,0,3");
+        let droplet = parse_droplet(&input);
+
+        let pockets = droplet.air_pockets();
+        assert_eq!(pockets.len(), 1);
+        assert_eq!(pockets[0].volume, 1);
+        assert_eq!(pockets[0].min, Point::new(-5, -5, -5));
+        assert_eq!(pockets[0].max, Point::new(-5, -5, -5));
+        assert_eq!(droplet.lava_components(), 2);
+        assert_eq!(droplet.surface_area(), 66);
+        assert_eq!(droplet.exterior_surface_area(), 60);
+    }
+}
@@ -1,8 +1,14 @@
 use aoc_runner_derive::aoc;
 use nom::{bytes::complete::tag, character::complete::u64, sequence::tuple, IResult};
+use std::collections::HashSet;
+#[cfg(feature = "parallel")]
+use std::{
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
 
-#[derive(Clone, Copy, Debug)]
-enum RobotKind {
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RobotKind {
     Ore,
     Clay,
     Obsidian,
@@ -16,16 +22,16 @@ struct Cost {
     obsidian: usize,
 }
 
-#[derive(Clone, Copy, Debug)]
-struct Resources {
-    ore: usize,
-    ore_robots: usize,
-    clay: usize,
-    clay_robots: usize,
-    obsidian: usize,
-    obsidian_robots: usize,
-    geodes: usize,
-    geode_robots: usize,
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Resources {
+    pub ore: usize,
+    pub ore_robots: usize,
+    pub clay: usize,
+    pub clay_robots: usize,
+    pub obsidian: usize,
+    pub obsidian_robots: usize,
+    pub geodes: usize,
+    pub geode_robots: usize,
 }
 
 impl Resources {
@@ -42,18 +48,34 @@ impl Resources {
         }
     }
 
-    fn collect(self) -> Self {
+    fn collect_for(self, minutes: usize) -> Self {
         Resources {
-            ore: self.ore + self.ore_robots,
-            clay: self.clay + self.clay_robots,
-            obsidian: self.obsidian + self.obsidian_robots,
-            geodes: self.geodes + self.geode_robots,
+            ore: self.ore + self.ore_robots * minutes,
+            clay: self.clay + self.clay_robots * minutes,
+            obsidian: self.obsidian + self.obsidian_robots * minutes,
+            geodes: self.geodes + self.geode_robots * minutes,
             ..self
         }
     }
 
-    fn can_build(self, cost: Cost) -> bool {
-        self.ore >= cost.ore && self.clay >= cost.clay && self.obsidian >= cost.obsidian
+    /// Number of minutes of collecting needed before the cost can be paid, if it ever can
+    fn minutes_until_affordable(self, cost: Cost) -> Option<usize> {
+        [
+            (self.ore, self.ore_robots, cost.ore),
+            (self.clay, self.clay_robots, cost.clay),
+            (self.obsidian, self.obsidian_robots, cost.obsidian),
+        ]
+        .into_iter()
+        .map(|(stock, robots, needed)| {
+            if stock >= needed {
+                Some(0)
+            } else if robots == 0 {
+                None
+            } else {
+                Some((needed - stock).div_ceil(robots))
+            }
+        })
+        .try_fold(0, |wait, minutes| minutes.map(|minutes| wait.max(minutes)))
     }
 
     fn build(self, robot: RobotKind, cost: Cost) -> Self {
@@ -97,7 +119,46 @@ impl Resources {
     }
 }
 
-struct Factory {
+/// A robot finished at the end of `minute`, with `resources` on hand once it was paid for
+#[derive(Clone, Copy, Debug)]
+pub struct BuildStep {
+    pub minute: usize,
+    pub robot: RobotKind,
+    pub resources: Resources,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BuildPlan {
+    pub geodes: usize,
+    pub steps: Vec<BuildStep>,
+}
+
+impl BuildPlan {
+    pub fn timeline(&self) -> String {
+        let mut timeline = String::new();
+        for step in self.steps.iter() {
+            let r = step.resources;
+            timeline.push_str(&format!(
+                "Minute {:>2}: {:?} robot -> robots {}/{}/{}/{}, stock {}/{}/{}/{}\n",
+                step.minute,
+                step.robot,
+                r.ore_robots,
+                r.clay_robots,
+                r.obsidian_robots,
+                r.geode_robots,
+                r.ore,
+                r.clay,
+                r.obsidian,
+                r.geodes,
+            ));
+        }
+        timeline.push_str(&format!("Total: {} geodes\n", self.geodes));
+
+        timeline
+    }
+}
+
+pub struct Factory {
     ore_robot: Cost,
     clay_robot: Cost,
     obsidian_robot: Cost,
@@ -137,65 +198,122 @@ impl Factory {
     }
 
     fn reasonable_to_build(&self, kind: RobotKind, resources: Resources) -> bool {
-        let cost = self.cost(kind);
+        // We can only spend one robot's worth of each resource per minute, so more robots are wasted
+        match kind {
+            RobotKind::Ore => resources.ore_robots < self.max_ore,
+            RobotKind::Clay => resources.clay_robots < self.max_clay,
+            RobotKind::Obsidian => resources.obsidian_robots < self.max_obsidian,
+            RobotKind::Geode => true,
+        }
+    }
 
-        let over_resourced = match kind {
-            RobotKind::Ore => resources.ore_robots > self.max_ore,
-            RobotKind::Clay => resources.clay_robots > self.max_clay,
-            RobotKind::Obsidian => resources.obsidian_robots > self.max_obsidian,
-            RobotKind::Geode => false,
+    /// Caps stockpiles at the most that could still be spent, so equivalent states compare equal
+    fn normalize(&self, resources: Resources, time_remaining: usize) -> Resources {
+        let cap = |max: usize, robots: usize| {
+            (max * time_remaining).saturating_sub(robots * time_remaining.saturating_sub(1))
         };
 
-        !over_resourced
-            && (cost.ore == 0 || resources.ore_robots > 0)
-            && (cost.clay == 0 || resources.clay_robots > 0)
-            && (cost.obsidian == 0 || resources.obsidian_robots > 0)
+        Resources {
+            ore: resources.ore.min(cap(self.max_ore, resources.ore_robots)),
+            clay: resources
+                .clay
+                .min(cap(self.max_clay, resources.clay_robots)),
+            obsidian: resources
+                .obsidian
+                .min(cap(self.max_obsidian, resources.obsidian_robots)),
+            ..resources
+        }
     }
 
-    fn next_build_options(&self, resources: Resources) -> Vec<RobotKind> {
-        [
-            RobotKind::Ore,
-            RobotKind::Clay,
-            RobotKind::Obsidian,
-            RobotKind::Geode,
-        ]
-        .into_iter()
-        .filter(|kind| self.reasonable_to_build(*kind, resources))
-        .collect()
+    /// An upper bound on the geodes reachable from here, pretending ore and clay are free so an
+    /// obsidian robot can be built every minute alongside any affordable geode robot
+    fn optimistic_geodes(&self, resources: Resources, time_remaining: usize) -> usize {
+        let mut obsidian = resources.obsidian;
+        let mut geodes = resources.geodes;
+        let mut geode_robots = resources.geode_robots;
+
+        for minute in 0..time_remaining {
+            let obsidian_robots = resources.obsidian_robots + minute;
+            geodes += geode_robots;
+            if obsidian >= self.geode_robot.obsidian {
+                obsidian = obsidian + obsidian_robots - self.geode_robot.obsidian;
+                geode_robots += 1;
+            } else {
+                obsidian += obsidian_robots;
+            }
+        }
+
+        geodes
     }
 
-    fn maximize(&self, time: usize) -> usize {
-        let resources = Resources::new();
-        self.next_build_options(resources)
-            .into_iter()
-            .map(|kind| self.maximum_geodes(resources, kind, time))
-            .max()
-            .unwrap()
+    pub fn maximize(&self, time: usize) -> BuildPlan {
+        let mut best = BuildPlan::default();
+        let mut path = Vec::new();
+        let mut seen = HashSet::new();
+        self.maximum_geodes(
+            Resources::new(),
+            time,
+            time,
+            &mut path,
+            &mut best,
+            &mut seen,
+        );
+        best
     }
 
     fn maximum_geodes(
         &self,
         resources: Resources,
-        active: RobotKind,
+        time: usize,
         time_remaining: usize,
-    ) -> usize {
-        if time_remaining == 0 {
-            return resources.geodes;
+        path: &mut Vec<BuildStep>,
+        best: &mut BuildPlan,
+        seen: &mut HashSet<(Resources, usize)>,
+    ) {
+        if !seen.insert((self.normalize(resources, time_remaining), time_remaining)) {
+            return;
         }
 
-        let active_cost = self.cost(active);
-        let can_build = resources.can_build(active_cost);
-        let resources = resources.collect();
-
-        if can_build {
-            let resources = resources.build(active, active_cost);
-            self.next_build_options(resources)
-                .into_iter()
-                .map(|new_active| self.maximum_geodes(resources, new_active, time_remaining - 1))
-                .max()
-                .unwrap()
-        } else {
-            self.maximum_geodes(resources, active, time_remaining - 1)
+        // Doing nothing for the rest of the time is always an option
+        let idle = resources.geodes + resources.geode_robots * time_remaining;
+        if idle > best.geodes {
+            best.geodes = idle;
+            best.steps = path.clone();
+        }
+
+        if self.optimistic_geodes(resources, time_remaining) <= best.geodes {
+            return;
+        }
+
+        for kind in [
+            RobotKind::Geode,
+            RobotKind::Obsidian,
+            RobotKind::Clay,
+            RobotKind::Ore,
+        ] {
+            if !self.reasonable_to_build(kind, resources) {
+                continue;
+            }
+
+            let cost = self.cost(kind);
+            let Some(wait) = resources.minutes_until_affordable(cost) else {
+                continue;
+            };
+
+            // A robot finished on the last minute can't collect anything
+            if wait + 1 >= time_remaining {
+                continue;
+            }
+
+            let resources = resources.collect_for(wait + 1).build(kind, cost);
+            let time_remaining = time_remaining - wait - 1;
+            path.push(BuildStep {
+                minute: time - time_remaining,
+                robot: kind,
+                resources,
+            });
+            self.maximum_geodes(resources, time, time_remaining, path, best, seen);
+            path.pop();
         }
     }
 }
@@ -205,7 +323,7 @@ fn parse_usize(input: &str) -> IResult<&str, usize> {
     Ok((rest, value as usize))
 }
 
-fn parse_factory(input: &str) -> (usize, Factory) {
+pub fn parse_factory(input: &str) -> (usize, Factory) {
     let (
         _,
         (
@@ -270,24 +388,182 @@ fn parse_factory(input: &str) -> (usize, Factory) {
     (id, factory)
 }
 
+#[cfg(not(feature = "parallel"))]
+fn maximize_all(factories: &[(usize, Factory)], time: usize) -> Vec<BuildPlan> {
+    factories
+        .iter()
+        .map(|(_, factory)| factory.maximize(time))
+        .collect()
+}
+
+/// Spreads the blueprints across one thread per core, with each thread pulling the next
+/// unclaimed blueprint so a single slow one doesn't hold up a whole batch
+#[cfg(feature = "parallel")]
+fn maximize_all(factories: &[(usize, Factory)], time: usize) -> Vec<BuildPlan> {
+    let workers = thread::available_parallelism().map_or(1, |count| count.get());
+    let next = AtomicUsize::new(0);
+
+    let mut plans: Vec<(usize, BuildPlan)> = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers.min(factories.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut plans = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        match factories.get(index) {
+                            Some((_, factory)) => plans.push((index, factory.maximize(time))),
+                            None => break plans,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    plans.sort_by_key(|(index, _)| *index);
+    plans.into_iter().map(|(_, plan)| plan).collect()
+}
+
+/// Renders the best build order for every blueprint, along with its quality level
+pub fn quality_report(input: &str, time: usize) -> String {
+    let factories: Vec<_> = input.lines().map(parse_factory).collect();
+    let plans = maximize_all(&factories, time);
+
+    let mut report = String::new();
+    for (&(id, _), plan) in factories.iter().zip(plans) {
+        report.push_str(&format!(
+            "Blueprint {}: quality level {}\n{}",
+            id,
+            id * plan.geodes,
+            plan.timeline()
+        ));
+    }
+
+    report
+}
+
 #[aoc(day19, part1)]
 fn solve_part1(input: &str) -> usize {
-    input
-        .lines()
-        .map(parse_factory)
-        .map(|(id, factory)| {
-            let geodes = factory.maximize(24);
-            id * geodes
-        })
+    let factories: Vec<_> = input.lines().map(parse_factory).collect();
+
+    factories
+        .iter()
+        .zip(maximize_all(&factories, 24))
+        .map(|((id, _), plan)| id * plan.geodes)
         .sum()
 }
 
 #[aoc(day19, part2)]
 fn solve_part2(input: &str) -> usize {
-    input
-        .lines()
-        .take(3)
-        .map(parse_factory)
-        .map(|(_, factory)| factory.maximize(32))
+    let factories: Vec<_> = input.lines().take(3).map(parse_factory).collect();
+
+    maximize_all(&factories, 32)
+        .into_iter()
+        .map(|plan| plan.geodes)
         .product()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "Blueprint 1: Each ore robot costs 4 ore. Each clay robot costs 2 ore. Each obsidian robot costs 3 ore and 14 clay. Each geode robot costs 2 ore and 7 obsidian.
+Blueprint 2: Each ore robot costs 2 ore. Each clay robot costs 3 ore. Each obsidian robot costs 3 ore and 8 clay. Each geode robot costs 3 ore and 12 obsidian.";
+
+    #[test]
+    fn test_example_geodes() {
+        let factories: Vec<_> = EXAMPLE.lines().map(parse_factory).collect();
+
+        let geodes = |time| {
+            factories
+                .iter()
+                .map(|(_, factory)| factory.maximize(time).geodes)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(geodes(24), vec![9, 12]);
+        assert_eq!(geodes(32), vec![56, 62]);
+    }
+
+    #[test]
+    fn test_example_build_order() {
+        let (_, factory) = parse_factory(EXAMPLE.lines().next().unwrap());
+        let plan = factory.maximize(24);
+
+        assert_eq!(
+            plan.steps
+                .iter()
+                .map(|step| (step.minute, step.robot))
+                .collect::<Vec<_>>(),
+            vec![
+                (3, RobotKind::Clay),
+                (5, RobotKind::Clay),
+                (7, RobotKind::Clay),
+                (11, RobotKind::Obsidian),
+                (12, RobotKind::Clay),
+                (15, RobotKind::Obsidian),
+                (18, RobotKind::Geode),
+                (21, RobotKind::Geode),
+            ]
+        );
+
+        // Replaying the steps minute by minute should end up with the promised geodes
+        let mut resources = Resources::new();
+        let mut steps = plan.steps.iter().peekable();
+        for minute in 1..=24 {
+            match steps.next_if(|step| step.minute == minute) {
+                Some(step) => {
+                    let cost = factory.cost(step.robot);
+                    assert_eq!(resources.minutes_until_affordable(cost), Some(0));
+                    resources = resources.collect_for(1).build(step.robot, cost);
+                    assert_eq!(resources, step.resources);
+                }
+                None => resources = resources.collect_for(1),
+            }
+        }
+        assert!(steps.next().is_none());
+        assert_eq!(resources.geodes, plan.geodes);
+    }
+
+    /// Only exercises the threaded path when run with `--features parallel`
+    #[test]
+    fn test_maximize_all_matches_sequential() {
+        let input = format!(
+            "{}
+Blueprint 3: Each ore robot costs 2 ore. Each clay robot costs 2 ore. Each obsidian robot costs 2 ore and 15 clay. Each geode robot costs 2 ore and 20 obsidian.
+Blueprint 4: Each ore robot costs 3 ore. Each clay robot costs 4 ore. Each obsidian robot costs 4 ore and 18 clay. Each geode robot costs 3 ore and 8 obsidian.
+Blueprint 5: Each ore robot costs 4 ore. Each clay robot costs 4 ore. Each obsidian robot costs 4 ore and 5 clay. Each geode robot costs 3 ore and 15 obsidian.
+Blueprint 6: Each ore robot costs 3 ore. Each clay robot costs 3 ore. Each obsidian robot costs 2 ore and 11 clay. Each geode robot costs 2 ore and 19 obsidian.",
+            EXAMPLE
+        );
+        let factories: Vec<_> = input.lines().map(parse_factory).collect();
+
+        let sequential: Vec<_> = factories
+            .iter()
+            .map(|(_, factory)| factory.maximize(24).timeline())
+            .collect();
+        let all: Vec<_> = maximize_all(&factories, 24)
+            .iter()
+            .map(BuildPlan::timeline)
+            .collect();
+        assert_eq!(all, sequential);
+
+        let expected_report: String = factories
+            .iter()
+            .zip(sequential)
+            .map(|((id, factory), timeline)| {
+                format!(
+                    "Blueprint {}: quality level {}\n{}",
+                    id,
+                    id * factory.maximize(24).geodes,
+                    timeline
+                )
+            })
+            .collect();
+        assert_eq!(quality_report(&input, 24), expected_report);
+    }
+}
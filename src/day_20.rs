@@ -1,9 +1,223 @@
 use aoc_runner_derive::aoc;
-use indexmap::IndexSet;
 
-struct Encrypted {
+const NIL: usize = usize::MAX;
+const DECRYPTION_KEY: isize = 811589153;
+const GROVE_OFFSETS: [usize; 3] = [1000, 2000, 3000];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum DecryptError {
+    /// The anchor value doesn't appear anywhere in the file
+    MissingAnchor(isize),
+}
+
+#[derive(Clone, Copy)]
+struct Node {
+    left: usize,
+    right: usize,
+    parent: usize,
+    size: usize,
+    priority: u64,
+}
+
+/// An implicit treap over the indices of the original values, ordered by their current position.
+/// Each node knows its parent, so finding and moving a value takes O(log n).
+struct OrderTree {
+    root: usize,
+    nodes: Vec<Node>,
+}
+
+impl OrderTree {
+    /// Builds a tree holding the nodes `0..count` in the given order
+    fn new(count: usize, order: impl Iterator<Item = usize>) -> Self {
+        // Fixed xorshift sequence, so the tree shape (and the runtime) is the same every run
+        let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+        let nodes = (0..count)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                Node {
+                    left: NIL,
+                    right: NIL,
+                    parent: NIL,
+                    size: 1,
+                    priority: seed,
+                }
+            })
+            .collect();
+
+        let mut tree = OrderTree { root: NIL, nodes };
+        for node in order {
+            tree.root = tree.merge(tree.root, node);
+        }
+        if tree.root != NIL {
+            tree.nodes[tree.root].parent = NIL;
+        }
+
+        tree
+    }
+
+    fn size_of(&self, node: usize) -> usize {
+        if node == NIL {
+            0
+        } else {
+            self.nodes[node].size
+        }
+    }
+
+    fn update(&mut self, node: usize) {
+        let Node { left, right, .. } = self.nodes[node];
+        self.nodes[node].size = 1 + self.size_of(left) + self.size_of(right);
+
+        if left != NIL {
+            self.nodes[left].parent = node;
+        }
+        if right != NIL {
+            self.nodes[right].parent = node;
+        }
+    }
+
+    fn merge(&mut self, first: usize, second: usize) -> usize {
+        if first == NIL {
+            return second;
+        }
+        if second == NIL {
+            return first;
+        }
+
+        if self.nodes[first].priority > self.nodes[second].priority {
+            self.nodes[first].right = self.merge(self.nodes[first].right, second);
+            self.update(first);
+            first
+        } else {
+            self.nodes[second].left = self.merge(first, self.nodes[second].left);
+            self.update(second);
+            second
+        }
+    }
+
+    /// Splits the tree into the first `count` nodes and the rest
+    fn split(&mut self, node: usize, count: usize) -> (usize, usize) {
+        if node == NIL {
+            return (NIL, NIL);
+        }
+
+        let left_size = self.size_of(self.nodes[node].left);
+        if count <= left_size {
+            let (first, second) = self.split(self.nodes[node].left, count);
+            self.nodes[node].left = second;
+            self.update(node);
+            (first, node)
+        } else {
+            let (first, second) = self.split(self.nodes[node].right, count - left_size - 1);
+            self.nodes[node].right = first;
+            self.update(node);
+            (node, second)
+        }
+    }
+
+    fn position(&self, node: usize) -> usize {
+        let mut position = self.size_of(self.nodes[node].left);
+        let mut current = node;
+
+        while self.nodes[current].parent != NIL {
+            let parent = self.nodes[current].parent;
+            if self.nodes[parent].right == current {
+                position += self.size_of(self.nodes[parent].left) + 1;
+            }
+            current = parent;
+        }
+
+        position
+    }
+
+    fn nth(&self, mut position: usize) -> usize {
+        let mut node = self.root;
+
+        loop {
+            let Node { left, right, .. } = self.nodes[node];
+            let left_size = self.size_of(left);
+            if position < left_size {
+                node = left;
+            } else if position == left_size {
+                return node;
+            } else {
+                position -= left_size + 1;
+                node = right;
+            }
+        }
+    }
+
+    /// Every node, in order
+    fn order(&self) -> Vec<usize> {
+        let mut order = Vec::with_capacity(self.nodes.len());
+        let mut stack = Vec::new();
+        let mut node = self.root;
+
+        while node != NIL || !stack.is_empty() {
+            while node != NIL {
+                stack.push(node);
+                node = self.nodes[node].left;
+            }
+
+            node = stack.pop().unwrap();
+            order.push(node);
+            node = self.nodes[node].right;
+        }
+
+        order
+    }
+
+    /// Unlinks the node by merging its children into its place, leaving it as a lone node
+    fn remove(&mut self, node: usize) {
+        let Node {
+            left,
+            right,
+            parent,
+            ..
+        } = self.nodes[node];
+        let replacement = self.merge(left, right);
+
+        if replacement != NIL {
+            self.nodes[replacement].parent = parent;
+        }
+        if parent == NIL {
+            self.root = replacement;
+        } else if self.nodes[parent].left == node {
+            self.nodes[parent].left = replacement;
+        } else {
+            self.nodes[parent].right = replacement;
+        }
+
+        let mut current = parent;
+        while current != NIL {
+            self.nodes[current].size -= 1;
+            current = self.nodes[current].parent;
+        }
+
+        self.nodes[node] = Node {
+            left: NIL,
+            right: NIL,
+            parent: NIL,
+            size: 1,
+            ..self.nodes[node]
+        };
+    }
+
+    /// Removes the node from its current position and reinserts it so it ends up at `end`
+    fn move_to(&mut self, node: usize, end: usize) {
+        self.remove(node);
+
+        let (before, after) = self.split(self.root, end);
+        let before = self.merge(before, node);
+        self.root = self.merge(before, after);
+        self.nodes[self.root].parent = NIL;
+    }
+}
+
+pub struct Encrypted {
     original: Vec<isize>,
-    current: IndexSet<(usize, isize)>,
+    current: OrderTree,
 }
 
 impl Encrypted {
@@ -25,70 +239,240 @@ impl Encrypted {
         index % count
     }
 
-    fn decrypt_pass(&mut self) {
+    /// Rebuilds a file from the original values and its current order, given as indices into
+    /// `original`
+    pub fn from_mixed(original: Vec<isize>, order: &[usize]) -> Self {
+        assert_eq!(original.len(), order.len(), "Every value must appear once");
+        let mut seen = vec![false; order.len()];
+        for &index in order {
+            assert!(
+                index < seen.len() && !seen[index],
+                "Every value must appear once"
+            );
+            seen[index] = true;
+        }
+
+        let current = OrderTree::new(original.len(), order.iter().copied());
+
+        Encrypted { original, current }
+    }
+
+    /// The current order of the file, as indices into the original values
+    pub fn order(&self) -> Vec<usize> {
+        self.current.order()
+    }
+
+    pub fn values(&self) -> Vec<isize> {
+        self.order()
+            .into_iter()
+            .map(|index| self.original[index])
+            .collect()
+    }
+
+    pub fn decrypt_pass(&mut self) {
+        // With a single value there is nowhere to move to
+        if self.original.len() < 2 {
+            return;
+        }
+
         for (index, &value) in self.original.iter().enumerate() {
-            let start_index = self.current.get_index_of(&(index, value)).unwrap();
+            let start_index = self.current.position(index);
             let end_index = self.calc_end_index(start_index, value);
 
-            self.current.move_index(start_index, end_index);
+            self.current.move_to(index, end_index);
+        }
+    }
+
+    /// Undoes one call to `decrypt_pass` by moving every value back, last one first. The file is
+    /// circular, so the result matches the earlier order up to a rotation.
+    pub fn unmix_pass(&mut self) {
+        if self.original.len() < 2 {
+            return;
+        }
+
+        for (index, &value) in self.original.iter().enumerate().rev() {
+            let end_index = self.current.position(index);
+            let start_index = self.calc_end_index(end_index, -value);
+
+            self.current.move_to(index, start_index);
         }
     }
 
-    fn coordinates(&self) -> (isize, isize, isize) {
-        let entry = self.current.iter().find(|(_, value)| *value == 0).unwrap();
-        let start = self.current.get_index_of(entry).unwrap();
-        let first_index = self.calc_index(start + 1000);
-        let second_index = self.calc_index(start + 2000);
-        let third_index = self.calc_index(start + 3000);
+    /// The values found `offsets` positions after the first occurrence of `anchor`
+    fn coordinates(&self, anchor: isize, offsets: &[usize]) -> Result<Vec<isize>, DecryptError> {
+        let anchor_index = self
+            .original
+            .iter()
+            .position(|value| *value == anchor)
+            .ok_or(DecryptError::MissingAnchor(anchor))?;
+        let start = self.current.position(anchor_index);
 
-        (
-            self.current.get_index(first_index).unwrap().1,
-            self.current.get_index(second_index).unwrap().1,
-            self.current.get_index(third_index).unwrap().1,
-        )
+        Ok(offsets
+            .iter()
+            .map(|offset| {
+                self.original[self
+                    .current
+                    .nth(self.calc_index(start + offset % self.original.len()))]
+            })
+            .collect())
     }
 }
 
 impl FromIterator<isize> for Encrypted {
     fn from_iter<T: IntoIterator<Item = isize>>(iter: T) -> Self {
-        let mut encrypted = Encrypted {
-            original: Vec::new(),
-            current: IndexSet::new(),
-        };
+        let original: Vec<isize> = iter.into_iter().collect();
+        let current = OrderTree::new(original.len(), 0..original.len());
 
-        for (index, value) in iter.into_iter().enumerate() {
-            encrypted.original.push(value);
-            encrypted.current.insert((index, value));
-        }
+        Encrypted { original, current }
+    }
+}
+
+/// Multiplies every value by `key`, mixes the file `rounds` times and then reads the values
+/// `offsets` positions after `anchor`, wrapping around the file. The anchor is matched against the
+/// multiplied values, using the earliest one in the original order if it appears more than once.
+pub fn decrypt(
+    values: &[isize],
+    key: isize,
+    rounds: usize,
+    anchor: isize,
+    offsets: &[usize],
+) -> Result<Vec<isize>, DecryptError> {
+    let mut encrypted: Encrypted = values.iter().map(|value| key * value).collect();
 
-        encrypted
+    for _ in 0..rounds {
+        encrypted.decrypt_pass();
     }
+
+    encrypted.coordinates(anchor, offsets)
 }
 
-fn parse_encrypted(input: &str, key: isize) -> Encrypted {
+pub fn parse_values(input: &str) -> Vec<isize> {
     input
         .lines()
-        .map(|line| key * line.parse::<isize>().unwrap())
+        .map(|line| line.parse::<isize>().unwrap())
         .collect()
 }
 
 #[aoc(day20, part1)]
 fn solve_part1(input: &str) -> isize {
-    let mut encrypted = parse_encrypted(input, 1);
-    encrypted.decrypt_pass();
+    let values = parse_values(input);
+    let coordinates = decrypt(&values, 1, 1, 0, &GROVE_OFFSETS).unwrap();
 
-    let (first, second, third) = encrypted.coordinates();
-    first + second + third
+    coordinates.into_iter().sum()
 }
 
 #[aoc(day20, part2)]
 fn solve_part2(input: &str) -> isize {
-    let mut encrypted = parse_encrypted(input, 811589153);
+    let values = parse_values(input);
+    let coordinates = decrypt(&values, DECRYPTION_KEY, 10, 0, &GROVE_OFFSETS).unwrap();
 
-    for _ in 0..10 {
-        encrypted.decrypt_pass();
+    coordinates.into_iter().sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decrypt_edge_cases() {
+        assert_eq!(decrypt(&[5], 1, 3, 5, &[0, 1, 7]), Ok(vec![5, 5, 5]));
+        assert_eq!(
+            decrypt(&[1, 2, 3], 1, 1, 0, &[1]),
+            Err(DecryptError::MissingAnchor(0))
+        );
+
+        // Duplicates mix independently and the anchor is the first 2 from the input
+        assert_eq!(decrypt(&[2, 0, 2], 1, 1, 2, &[0, 1, 2]), Ok(vec![2, 0, 2]));
+
+        let example = [1, 2, -3, 3, -2, 0, 4];
+        assert_eq!(
+            decrypt(&example, 1, 1, 0, &GROVE_OFFSETS),
+            Ok(vec![4, -3, 2])
+        );
+        assert_eq!(
+            decrypt(&example, 1, 1, 0, &[usize::MAX, usize::MAX - 7]),
+            decrypt(&example, 1, 1, 0, &[usize::MAX % 7, usize::MAX % 7])
+        );
+    }
+
+    #[test]
+    fn test_unmix_round_trip() {
+        // Compares orders as circular lists, starting from the first original value
+        let normalize = |mut order: Vec<usize>| {
+            let start = order.iter().position(|&index| index == 0).unwrap();
+            order.rotate_left(start);
+            order
+        };
+
+        let mut seed = 0x9e37_79b9_u64;
+        let mut random = move |limit: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % limit
+        };
+
+        for _ in 0..200 {
+            let count = random(40) as usize + 1;
+            let values: Vec<isize> = (0..count).map(|_| random(41) as isize - 20).collect();
+            let rounds = random(4) + 1;
+
+            let mut encrypted: Encrypted = values.iter().copied().collect();
+            let mut states = vec![encrypted.order()];
+            for _ in 0..rounds {
+                encrypted.decrypt_pass();
+                states.push(encrypted.order());
+            }
+
+            let mut restored = Encrypted::from_mixed(values.clone(), &encrypted.order());
+            for state in states.into_iter().rev().skip(1) {
+                restored.unmix_pass();
+                assert_eq!(normalize(restored.order()), normalize(state));
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Every value must appear once")]
+    fn test_from_mixed_rejects_repeated_index() {
+        Encrypted::from_mixed(vec![1, 2], &[0, 0]);
     }
 
-    let (first, second, third) = encrypted.coordinates();
-    first + second + third
+    #[test]
+    fn test_matches_vec_mixing() {
+        // The original approach: remove each value and reinsert it at its wrapped position
+        fn mix(values: &[isize], order: &mut Vec<usize>) {
+            let count = values.len() as isize - 1;
+            for (index, &value) in values.iter().enumerate() {
+                let start = order.iter().position(|&other| other == index).unwrap();
+                let end = (start as isize + value).rem_euclid(count) as usize;
+                order.remove(start);
+                order.insert(end, index);
+            }
+        }
+
+        let mut seed = 0x85eb_ca6b_u64;
+        let mut random = move |limit: u64| {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) % limit
+        };
+
+        for _ in 0..200 {
+            let count = random(50) as usize + 2;
+            let key = if random(2) == 0 { 1 } else { DECRYPTION_KEY };
+            let values: Vec<isize> = (0..count)
+                .map(|_| (random(41) as isize - 20) * key)
+                .collect();
+
+            let mut encrypted: Encrypted = values.iter().copied().collect();
+            let mut expected: Vec<usize> = (0..count).collect();
+            for _ in 0..random(5) + 1 {
+                encrypted.decrypt_pass();
+                mix(&values, &mut expected);
+                assert_eq!(encrypted.order(), expected);
+            }
+        }
+    }
 }
@@ -1,12 +1,14 @@
 use aoc_runner_derive::aoc;
-use std::array::IntoIter;
 use std::collections::{HashMap, HashSet};
-use std::iter::Cycle;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-struct Point {
-    x: isize,
-    y: isize,
+const WORD_BITS: usize = u64::BITS as usize;
+/// Empty rows kept above and below the elves when the bitboard is (re)built
+const ROW_PADDING: usize = 32;
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Point {
+    pub x: isize,
+    pub y: isize,
 }
 
 impl Point {
@@ -14,24 +16,28 @@ impl Point {
         Point { x, y }
     }
 
-    fn neighbors(self, direction: Direction) -> [Self; 3] {
+    fn neighbors(self, direction: Direction, neighborhood: Neighborhood) -> Vec<Self> {
+        if neighborhood == Neighborhood::Target {
+            return vec![self.proposed_move(direction)];
+        }
+
         match direction {
-            Direction::North => [
+            Direction::North => vec![
                 Point::new(self.x - 1, self.y - 1),
                 Point::new(self.x, self.y - 1),
                 Point::new(self.x + 1, self.y - 1),
             ],
-            Direction::South => [
+            Direction::South => vec![
                 Point::new(self.x - 1, self.y + 1),
                 Point::new(self.x, self.y + 1),
                 Point::new(self.x + 1, self.y + 1),
             ],
-            Direction::East => [
+            Direction::East => vec![
                 Point::new(self.x + 1, self.y - 1),
                 Point::new(self.x + 1, self.y),
                 Point::new(self.x + 1, self.y + 1),
             ],
-            Direction::West => [
+            Direction::West => vec![
                 Point::new(self.x - 1, self.y - 1),
                 Point::new(self.x - 1, self.y),
                 Point::new(self.x - 1, self.y + 1),
@@ -63,72 +69,112 @@ impl Point {
     }
 }
 
-#[derive(Clone, Copy)]
-enum Direction {
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
     North,
     South,
     East,
     West,
 }
 
-fn directions() -> Cycle<IntoIter<Direction, 4>> {
-    [
-        Direction::North,
-        Direction::South,
-        Direction::West,
-        Direction::East,
-    ]
-    .into_iter()
-    .cycle()
+/// How the proposal order changes from one round to the next
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rotation {
+    /// The first direction moves to the back after every round
+    Rotate,
+    /// The same order is used every round
+    Fixed,
+}
+
+/// Which cells must be empty for an elf to propose moving in a direction. Elves with any of the
+/// eight surrounding cells occupied still propose a move under either rule.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Neighborhood {
+    /// The three cells on that side of the elf
+    Side,
+    /// Only the cell the elf would move into
+    Target,
+}
+
+/// What happens when more than one elf proposes the same spot
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Conflict {
+    /// None of them move
+    Cancel,
+    /// The elf whose direction comes earliest in this round's order moves, the rest stay
+    Priority,
+}
+
+#[derive(Clone, Debug)]
+pub struct Rules {
+    pub order: Vec<Direction>,
+    pub rotation: Rotation,
+    pub neighborhood: Neighborhood,
+    pub conflict: Conflict,
+}
+
+impl Default for Rules {
+    fn default() -> Self {
+        Rules {
+            order: vec![
+                Direction::North,
+                Direction::South,
+                Direction::West,
+                Direction::East,
+            ],
+            rotation: Rotation::Rotate,
+            neighborhood: Neighborhood::Side,
+            conflict: Conflict::Cancel,
+        }
+    }
 }
 
-struct Forest {
+/// The straightforward simulation, which supports every rule set and is the reference for
+/// `BitForest`
+pub struct Forest {
     elves: HashSet<Point>,
-    directions: Cycle<IntoIter<Direction, 4>>,
+    rules: Rules,
+    round: usize,
 }
 
 impl Forest {
+    /// Whether all eight surrounding cells are empty, whatever the neighborhood rule
     fn is_alone(&self, elf: Point) -> bool {
-        [
-            Point::new(elf.x - 1, elf.y - 1),
-            Point::new(elf.x, elf.y - 1),
-            Point::new(elf.x + 1, elf.y - 1),
-            Point::new(elf.x + 1, elf.y),
-            Point::new(elf.x + 1, elf.y + 1),
-            Point::new(elf.x, elf.y + 1),
-            Point::new(elf.x - 1, elf.y + 1),
-            Point::new(elf.x - 1, elf.y),
-        ]
-        .into_iter()
-        .all(|point| !self.elves.contains(&point))
+        [Direction::North, Direction::South]
+            .into_iter()
+            .flat_map(|direction| elf.neighbors(direction, Neighborhood::Side))
+            .chain([Point::new(elf.x - 1, elf.y), Point::new(elf.x + 1, elf.y)])
+            .all(|point| !self.elves.contains(&point))
     }
 
     fn is_safe_direction(&self, elf: Point, direction: Direction) -> bool {
-        elf.neighbors(direction)
+        elf.neighbors(direction, self.rules.neighborhood)
             .into_iter()
             .all(|point| !self.elves.contains(&point))
     }
 
-    fn propose_moves(&mut self) -> HashMap<Point, Vec<Point>> {
-        let dirs = [
-            self.directions.next().unwrap(),
-            self.directions.next().unwrap(),
-            self.directions.next().unwrap(),
-            self.directions.next().unwrap(),
-        ];
-        // Rotate the directions for the next call
-        self.directions.next();
+    /// The proposed targets, each with the elves proposing it and their rank in this round's order
+    fn propose_moves(&mut self) -> HashMap<Point, Vec<(usize, Point)>> {
+        let mut dirs = self.rules.order.clone();
+        if self.rules.rotation == Rotation::Rotate && !dirs.is_empty() {
+            let len = dirs.len();
+            dirs.rotate_left(self.round % len);
+        }
+        self.round += 1;
 
-        let mut result: HashMap<Point, Vec<Point>> = HashMap::new();
+        let mut result: HashMap<Point, Vec<(usize, Point)>> = HashMap::new();
 
         for elf in self.elves.iter().copied() {
             if self.is_alone(elf) {
                 continue;
             }
 
-            for dir in dirs {
+            for (rank, &dir) in dirs.iter().enumerate() {
                 if self.is_safe_direction(elf, dir) {
-                    result.entry(elf.proposed_move(dir)).or_default().push(elf);
+                    result
+                        .entry(elf.proposed_move(dir))
+                        .or_default()
+                        .push((rank, elf));
                     break;
                 }
             }
@@ -137,19 +183,34 @@ impl Forest {
         result
     }
 
-    fn step(&mut self) -> bool {
+    pub fn elves(&self) -> &HashSet<Point> {
+        &self.elves
+    }
+
+    pub fn step(&mut self) -> bool {
         let mut did_move = false;
         for (target, elves) in self.propose_moves() {
-            if elves.len() == 1 {
+            let mover = match self.rules.conflict {
+                Conflict::Cancel if elves.len() > 1 => None,
+                _ => elves.iter().min_by_key(|(rank, _)| *rank),
+            };
+
+            if let Some(&(_, elf)) = mover {
                 did_move = true;
-                self.elves.remove(&elves[0]);
+                self.elves.remove(&elf);
                 self.elves.insert(target);
             }
         }
         did_move
     }
 
-    fn free_space(&self) -> usize {
+    /// Runs rounds until nobody moves, returning the number of the first such round, or `None`
+    /// if the elves are still moving after `limit` rounds
+    pub fn rounds_until_stable(&mut self, limit: usize) -> Option<usize> {
+        (1..=limit).find(|_| !self.step())
+    }
+
+    pub fn free_space(&self) -> usize {
         let min_point = Point::new(isize::MAX, isize::MAX);
         let max_point = Point::new(isize::MIN, isize::MIN);
 
@@ -164,7 +225,243 @@ impl Forest {
     }
 }
 
-fn parse_forest(input: &str) -> Forest {
+/// Bit x is set when cell x - `distance` is set
+fn from_west(row: &[u64], word: usize, distance: u32) -> u64 {
+    let carry = if word > 0 {
+        row[word - 1] >> (u64::BITS - distance)
+    } else {
+        0
+    };
+    (row[word] << distance) | carry
+}
+
+/// Bit x is set when cell x + `distance` is set
+fn from_east(row: &[u64], word: usize, distance: u32) -> u64 {
+    let carry = if word + 1 < row.len() {
+        row[word + 1] << (u64::BITS - distance)
+    } else {
+        0
+    };
+    (row[word] >> distance) | carry
+}
+
+/// The same simulation as `Forest` under the default rules, with every row stored as a bitset so
+/// that a round is a few shifts and masks per 64 cells. Proposal order is North, South, West, East
+/// as indices 0-3.
+pub struct BitForest {
+    /// Position of bit 0 in row 0
+    origin: Point,
+    words: usize,
+    height: usize,
+    rows: Vec<u64>,
+    first_direction: usize,
+}
+
+impl BitForest {
+    pub fn new(elves: &HashSet<Point>) -> Self {
+        let min_point = Point::new(isize::MAX, isize::MAX);
+        let max_point = Point::new(isize::MIN, isize::MIN);
+        let (min, max) = elves
+            .iter()
+            .fold((min_point, max_point), |(min, max), &point| {
+                (min.min(point), max.max(point))
+            });
+        let (min, max) = if elves.is_empty() {
+            (Point::new(0, 0), Point::new(0, 0))
+        } else {
+            (min, max)
+        };
+
+        // Leave a spare word on either side and some spare rows above and below
+        let width = (max.x - min.x + 1) as usize;
+        let words = width.div_ceil(WORD_BITS) + 2;
+        let height = (max.y - min.y + 1) as usize + 2 * ROW_PADDING;
+        let origin = Point::new(min.x - WORD_BITS as isize, min.y - ROW_PADDING as isize);
+
+        let mut forest = BitForest {
+            origin,
+            words,
+            height,
+            rows: vec![0; words * height],
+            first_direction: 0,
+        };
+
+        for elf in elves.iter() {
+            let x = (elf.x - origin.x) as usize;
+            let y = (elf.y - origin.y) as usize;
+            forest.rows[y * words + x / WORD_BITS] |= 1 << (x % WORD_BITS);
+        }
+
+        forest
+    }
+
+    fn row(&self, y: usize) -> &[u64] {
+        &self.rows[y * self.words..(y + 1) * self.words]
+    }
+
+    pub fn elves(&self) -> HashSet<Point> {
+        let mut elves = HashSet::new();
+        for y in 0..self.height {
+            for (word, &bits) in self.row(y).iter().enumerate() {
+                let mut remaining = bits;
+                while remaining != 0 {
+                    let x = word * WORD_BITS + remaining.trailing_zeros() as usize;
+                    remaining &= remaining - 1;
+                    elves.insert(Point::new(
+                        self.origin.x + x as isize,
+                        self.origin.y + y as isize,
+                    ));
+                }
+            }
+        }
+
+        elves
+    }
+
+    /// Whether any elf is on the outermost rows or columns, where it could step off the board
+    fn touches_edge(&self) -> bool {
+        let last = self.height - 1;
+        let high_bit = 1 << (WORD_BITS - 1);
+
+        self.row(0).iter().any(|&bits| bits != 0)
+            || self.row(last).iter().any(|&bits| bits != 0)
+            || (0..self.height).any(|y| {
+                let row = self.row(y);
+                row[0] & 1 != 0 || row[self.words - 1] & high_bit != 0
+            })
+    }
+
+    pub fn step(&mut self) -> bool {
+        if self.touches_edge() {
+            let first_direction = self.first_direction;
+            *self = BitForest::new(&self.elves());
+            self.first_direction = first_direction;
+        }
+
+        let (words, height) = (self.words, self.height);
+        let empty = vec![0; words];
+        // Proposals by direction (North, South, West, East), marked at the proposing elf
+        let mut proposals = vec![vec![0u64; words * height]; 4];
+
+        for y in 0..height {
+            let above = if y > 0 { self.row(y - 1) } else { &empty };
+            let current = self.row(y);
+            let below = if y + 1 < height {
+                self.row(y + 1)
+            } else {
+                &empty
+            };
+
+            for word in 0..words {
+                let (nw, n, ne) = (
+                    from_west(above, word, 1),
+                    above[word],
+                    from_east(above, word, 1),
+                );
+                let (w, e) = (from_west(current, word, 1), from_east(current, word, 1));
+                let (sw, s, se) = (
+                    from_west(below, word, 1),
+                    below[word],
+                    from_east(below, word, 1),
+                );
+
+                let free = [
+                    !(nw | n | ne),
+                    !(sw | s | se),
+                    !(nw | w | sw),
+                    !(ne | e | se),
+                ];
+                let alone = free[0] & free[1] & free[2] & free[3];
+
+                let mut remaining = current[word] & !alone;
+                for offset in 0..4 {
+                    let direction = (self.first_direction + offset) % 4;
+                    let proposing = remaining & free[direction];
+                    proposals[direction][y * words + word] = proposing;
+                    remaining &= !proposing;
+                }
+            }
+        }
+        self.first_direction = (self.first_direction + 1) % 4;
+
+        // Only elves moving in opposite directions can pick the same spot, two cells apart
+        let [north, south, west, east] = &proposals[..] else {
+            unreachable!()
+        };
+        let mut accepted = vec![vec![0u64; words * height]; 4];
+        for y in 0..height {
+            let range = y * words..(y + 1) * words;
+            for word in 0..words {
+                let index = y * words + word;
+                let south_two_up = if y >= 2 { south[index - 2 * words] } else { 0 };
+                let north_two_down = if y + 2 < height {
+                    north[index + 2 * words]
+                } else {
+                    0
+                };
+
+                accepted[0][index] = north[index] & !south_two_up;
+                accepted[1][index] = south[index] & !north_two_down;
+                accepted[2][index] = west[index] & !from_west(&east[range.clone()], word, 2);
+                accepted[3][index] = east[index] & !from_east(&west[range.clone()], word, 2);
+            }
+        }
+
+        let mut did_move = false;
+        let mut rows = vec![0; words * height];
+        for y in 0..height {
+            let range = y * words..(y + 1) * words;
+            for word in 0..words {
+                let index = y * words + word;
+                let leaving = accepted[0][index]
+                    | accepted[1][index]
+                    | accepted[2][index]
+                    | accepted[3][index];
+                did_move |= leaving != 0;
+
+                let from_below = if y + 1 < height {
+                    accepted[0][index + words]
+                } else {
+                    0
+                };
+                let from_above = if y > 0 { accepted[1][index - words] } else { 0 };
+
+                rows[index] = (self.rows[index] & !leaving)
+                    | from_below
+                    | from_above
+                    | from_east(&accepted[2][range.clone()], word, 1)
+                    | from_west(&accepted[3][range.clone()], word, 1);
+            }
+        }
+        self.rows = rows;
+
+        did_move
+    }
+
+    pub fn free_space(&self) -> usize {
+        let mut count = 0;
+        let (mut min_x, mut max_x) = (usize::MAX, 0);
+        let (mut min_y, mut max_y) = (usize::MAX, 0);
+
+        for y in 0..self.height {
+            for (word, &bits) in self.row(y).iter().enumerate() {
+                if bits == 0 {
+                    continue;
+                }
+
+                count += bits.count_ones() as usize;
+                min_x = min_x.min(word * WORD_BITS + bits.trailing_zeros() as usize);
+                max_x = max_x.max((word + 1) * WORD_BITS - 1 - bits.leading_zeros() as usize);
+                min_y = min_y.min(y);
+                max_y = max_y.max(y);
+            }
+        }
+
+        (max_x - min_x + 1) * (max_y - min_y + 1) - count
+    }
+}
+
+pub fn parse_forest(input: &str, rules: Rules) -> Forest {
     let elves: HashSet<_> = input
         .lines()
         .enumerate()
@@ -181,13 +478,14 @@ fn parse_forest(input: &str) -> Forest {
 
     Forest {
         elves,
-        directions: directions(),
+        rules,
+        round: 0,
     }
 }
 
 #[aoc(day23, part1)]
 fn solve_part1(input: &str) -> usize {
-    let mut forest = parse_forest(input);
+    let mut forest = BitForest::new(parse_forest(input, Rules::default()).elves());
 
     for _ in 0..10 {
         forest.step();
@@ -198,7 +496,7 @@ fn solve_part1(input: &str) -> usize {
 
 #[aoc(day23, part2)]
 fn solve_part2(input: &str) -> usize {
-    let mut forest = parse_forest(input);
+    let mut forest = BitForest::new(parse_forest(input, Rules::default()).elves());
     let mut round = 1;
 
     while forest.step() {
@@ -207,3 +505,111 @@ fn solve_part2(input: &str) -> usize {
 
     round
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bit_forest_matches_reference() {
+        let mut seed = 0x2f6b_3a91_u64;
+        for size in [3, 10, 30] {
+            let mut field = String::new();
+            for _ in 0..size {
+                for _ in 0..size {
+                    seed = seed
+                        .wrapping_mul(6364136223846793005)
+                        .wrapping_add(1442695040888963407);
+                    field.push(if (seed >> 33).is_multiple_of(3) {
+                        '#'
+                    } else {
+                        '.'
+                    });
+                }
+                field.push('\n');
+            }
+
+            let mut reference = parse_forest(&field, Rules::default());
+            let mut forest = BitForest::new(reference.elves());
+            loop {
+                let moved = reference.step();
+                assert_eq!(forest.step(), moved);
+                assert_eq!(&forest.elves(), reference.elves());
+                assert_eq!(forest.free_space(), reference.free_space());
+
+                if !moved {
+                    break;
+                }
+            }
+        }
+    }
+
+    fn elves(points: &[(isize, isize)]) -> HashSet<Point> {
+        points.iter().map(|&(x, y)| Point::new(x, y)).collect()
+    }
+
+    #[test]
+    fn test_conflict_rules() {
+        let field = ".....\n..##.\n..#..\n.....\n..##.\n.....";
+
+        // The elves at (2, 2) and (2, 4) both propose (2, 3)
+        let mut cancel = parse_forest(field, Rules::default());
+        cancel.step();
+        assert_eq!(
+            cancel.elves(),
+            &elves(&[(2, 0), (3, 0), (2, 2), (3, 3), (2, 4)])
+        );
+
+        // The lower elf proposed North, which comes before the upper elf's South
+        let mut priority = parse_forest(
+            field,
+            Rules {
+                conflict: Conflict::Priority,
+                ..Rules::default()
+            },
+        );
+        priority.step();
+        assert_eq!(
+            priority.elves(),
+            &elves(&[(2, 0), (3, 0), (2, 2), (2, 3), (3, 3)])
+        );
+    }
+
+    #[test]
+    fn test_rotation_rules() {
+        // Rotating, the pair goes north, comes back south, then splits west and east
+        let mut rotate = parse_forest("##", Rules::default());
+        assert_eq!(rotate.rounds_until_stable(10), Some(4));
+        assert_eq!(rotate.elves(), &elves(&[(-1, 0), (2, 0)]));
+
+        // With a fixed order they keep heading north together
+        let mut fixed = parse_forest(
+            "##",
+            Rules {
+                rotation: Rotation::Fixed,
+                ..Rules::default()
+            },
+        );
+        assert_eq!(fixed.rounds_until_stable(3), None);
+        assert_eq!(fixed.elves(), &elves(&[(0, -3), (1, -3)]));
+    }
+
+    #[test]
+    fn test_neighborhood_rules() {
+        // The elf at (0, 1) has a diagonal neighbor to the north-east
+        let mut side = parse_forest(".#\n#.", Rules::default());
+        side.step();
+        assert_eq!(side.elves(), &elves(&[(1, -1), (0, 2)]));
+
+        // Only the target cell matters, but the diagonal neighbor still means it isn't alone
+        let mut target = parse_forest(
+            ".#\n#.",
+            Rules {
+                neighborhood: Neighborhood::Target,
+                ..Rules::default()
+            },
+        );
+        target.step();
+        assert_eq!(target.elves(), &elves(&[(1, -1), (0, 0)]));
+    }
+}
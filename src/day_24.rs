@@ -1,15 +1,14 @@
 use crate::dijkstra::{Dijkstra, Value};
 use aoc_runner_derive::aoc;
-use std::collections::HashSet;
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
-struct Point {
-    row: isize,
-    col: isize,
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Point {
+    pub row: isize,
+    pub col: isize,
 }
 
 impl Point {
-    fn new(row: isize, col: isize) -> Self {
+    pub fn new(row: isize, col: isize) -> Self {
         Point { row, col }
     }
 
@@ -23,7 +22,7 @@ impl Point {
     }
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq, Eq)]
 enum Direction {
     Up,
     Down,
@@ -35,83 +34,210 @@ enum Direction {
 struct State {
     position: Point,
     time: usize,
+    /// `time` modulo the blizzard period, since the valley looks the same at both
+    phase: usize,
+    /// Number of waypoints reached so far
+    leg: usize,
+    /// Index of the most recent arrival on the way to this state, see `Arrival`
+    last_arrival: Option<usize>,
 }
 
 impl Value for State {
-    type Id = State;
+    type Id = (Point, usize, usize);
 
     fn id(&self) -> Self::Id {
-        *self
+        (self.position, self.phase, self.leg)
     }
 }
 
+/// A waypoint being reached, linked to the arrival before it. States share these rather than
+/// each carrying the full list of arrival times.
+struct Arrival {
+    time: usize,
+    previous: Option<usize>,
+}
+
+/// The fastest route through a list of waypoints
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Itinerary {
+    pub total_time: usize,
+    /// The minute each waypoint after the first was reached
+    pub arrivals: Vec<usize>,
+}
+
 #[derive(Clone, Copy)]
 struct Blizzard {
     start: Point,
     direction: Direction,
 }
 
-struct Snowfield {
-    blizzards: Vec<Blizzard>,
+pub struct Snowfield {
     start_col: isize,
     end_col: isize,
     max_row: isize,
     max_col: isize,
+    /// Number of minutes before every blizzard is back where it started
+    period: usize,
+    /// The blizzard starting in each cell of the valley, if any, row by row
+    starts: Vec<Option<Direction>>,
 }
 
-struct SnowfieldSnapshot {
-    blizzards: HashSet<Point>,
-    start_col: isize,
-    end_col: isize,
-    max_row: isize,
-    max_col: isize,
+fn gcd(a: usize, b: usize) -> usize {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
 }
 
 impl Snowfield {
-    fn shift_position(&self, start: Point, direction: Direction, time: usize) -> Point {
-        let updated = match direction {
-            Direction::Up => Point::new(start.row - time as isize, start.col),
-            Direction::Down => Point::new(start.row + time as isize, start.col),
-            Direction::Left => Point::new(start.row, start.col - time as isize),
-            Direction::Right => Point::new(start.row, start.col + time as isize),
-        };
-
-        let wrapped_row = (updated.row - 1).rem_euclid(self.max_row) + 1;
-        let wrapped_col = (updated.col - 1).rem_euclid(self.max_col) + 1;
+    fn new(
+        blizzards: Vec<Blizzard>,
+        start_col: isize,
+        end_col: isize,
+        max_row: isize,
+        max_col: isize,
+    ) -> Self {
+        let (rows, cols) = (max_row as usize, max_col as usize);
+        let mut starts = vec![None; rows * cols];
+        for blizzard in blizzards {
+            let Point { row, col } = blizzard.start;
+            starts[(row - 1) as usize * cols + (col - 1) as usize] = Some(blizzard.direction);
+        }
 
-        Point::new(wrapped_row, wrapped_col)
+        Snowfield {
+            start_col,
+            end_col,
+            max_row,
+            max_col,
+            period: rows / gcd(rows, cols) * cols,
+            starts,
+        }
     }
 
-    fn snapshot(&self, time: usize) -> SnowfieldSnapshot {
-        let blizzards = self
-            .blizzards
-            .iter()
-            .map(|blizz| self.shift_position(blizz.start, blizz.direction, time))
-            .collect();
-
-        SnowfieldSnapshot {
-            blizzards,
-            start_col: self.start_col,
-            end_col: self.end_col,
-            max_row: self.max_row,
-            max_col: self.max_col,
-        }
+    fn starts_with(&self, row: isize, col: isize, direction: Direction) -> bool {
+        let cell = (row - 1) as usize * self.max_col as usize + (col - 1) as usize;
+        self.starts[cell] == Some(direction)
     }
-}
 
-impl SnowfieldSnapshot {
-    fn is_open(&self, point: Point) -> bool {
-        (point.row >= 1
+    fn is_open(&self, point: Point, time: usize) -> bool {
+        if point.row >= 1
             && point.row <= self.max_row
             && point.col >= 1
             && point.col <= self.max_col
-            && !self.blizzards.contains(&point))
-            || (point.row == 0 && point.col == self.start_col)
-            || (point.row == self.max_row + 1 && point.col == self.end_col)
+        {
+            // Look for a blizzard that started `time` cells away, heading towards this one
+            let (row, col) = (point.row - 1, point.col - 1);
+            let (rows, cols) = (self.max_row, self.max_col);
+            let (row_shift, col_shift) = (
+                (time % rows as usize) as isize,
+                (time % cols as usize) as isize,
+            );
+
+            !self.starts_with(
+                point.row,
+                (col - col_shift).rem_euclid(cols) + 1,
+                Direction::Right,
+            ) && !self.starts_with(point.row, (col + col_shift) % cols + 1, Direction::Left)
+                && !self.starts_with(
+                    (row - row_shift).rem_euclid(rows) + 1,
+                    point.col,
+                    Direction::Down,
+                )
+                && !self.starts_with((row + row_shift) % rows + 1, point.col, Direction::Up)
+        } else {
+            (point.row == 0 && point.col == self.start_col)
+                || (point.row == self.max_row + 1 && point.col == self.end_col)
+        }
+    }
+
+    /// The gap in the top wall
+    pub fn start(&self) -> Point {
+        Point::new(0, self.start_col)
+    }
+
+    /// The gap in the bottom wall
+    pub fn end(&self) -> Point {
+        Point::new(self.max_row + 1, self.end_col)
+    }
+
+    /// Records an arrival for every upcoming waypoint at `position`
+    fn arrive(&self, state: &mut State, targets: &[Point], arrivals: &mut Vec<Arrival>) {
+        while targets.get(state.leg) == Some(&state.position) {
+            arrivals.push(Arrival {
+                time: state.time,
+                previous: state.last_arrival,
+            });
+            state.last_arrival = Some(arrivals.len() - 1);
+            state.leg += 1;
+        }
+    }
+
+    /// Finds the fastest way to visit every waypoint in order, setting off from the first one at
+    /// minute 0. Returns `None` if the first waypoint is blocked or some waypoint can't be reached.
+    pub fn itinerary(&self, waypoints: &[Point]) -> Option<Itinerary> {
+        let (&first, targets) = waypoints.split_first()?;
+        if !self.is_open(first, 0) {
+            return None;
+        }
+
+        let mut arrivals = Vec::new();
+        let mut start = State {
+            position: first,
+            time: 0,
+            phase: 0,
+            leg: 0,
+            last_arrival: None,
+        };
+        self.arrive(&mut start, targets, &mut arrivals);
+
+        let (end, _) = Dijkstra::new(
+            start,
+            |state| state.leg == targets.len(),
+            |state| {
+                let next_time = state.time + 1;
+
+                [
+                    state.position.shift(Direction::Down),
+                    state.position.shift(Direction::Right),
+                    state.position,
+                    state.position.shift(Direction::Up),
+                    state.position.shift(Direction::Left),
+                ]
+                .into_iter()
+                .filter(|&position| self.is_open(position, next_time))
+                .map(|position| {
+                    let mut next = State {
+                        position,
+                        time: next_time,
+                        phase: next_time % self.period,
+                        ..*state
+                    };
+                    self.arrive(&mut next, targets, &mut arrivals);
+
+                    (next, 1)
+                })
+                .collect::<Vec<_>>()
+            },
+        )
+        .next()?;
+
+        let mut times = Vec::new();
+        let mut current = end.last_arrival;
+        while let Some(index) = current {
+            times.push(arrivals[index].time);
+            current = arrivals[index].previous;
+        }
+        times.reverse();
+
+        Some(Itinerary {
+            total_time: end.time,
+            arrivals: times,
+        })
     }
 }
 
-fn parse_snowfield(input: &str) -> Snowfield {
+pub fn parse_snowfield(input: &str) -> Snowfield {
     let mut lines = input.lines();
 
     let start_col = lines
@@ -152,167 +278,59 @@ fn parse_snowfield(input: &str) -> Snowfield {
             blizzards.push(Blizzard { start, direction });
         }
     }
-    Snowfield {
-        blizzards,
-        start_col,
-        end_col,
-        max_row,
-        max_col,
-    }
+    Snowfield::new(blizzards, start_col, end_col, max_row, max_col)
 }
 
 #[aoc(day24, part1)]
 fn solve_part1(input: &str) -> usize {
     let snowfield = parse_snowfield(input);
-    let start_pos = Point::new(0, snowfield.start_col);
-    let start = State {
-        position: start_pos,
-        time: 0,
-    };
-
-    let (end, _) = Dijkstra::new(
-        start,
-        |state| state.position == Point::new(snowfield.max_row + 1, snowfield.end_col),
-        |state| {
-            let next_time = state.time + 1;
-            let snapshot = snowfield.snapshot(next_time);
-
-            [
-                state.position.shift(Direction::Down),
-                state.position.shift(Direction::Right),
-                state.position,
-                state.position.shift(Direction::Up),
-                state.position.shift(Direction::Left),
-            ]
-            .into_iter()
-            .filter_map(move |position| {
-                if snapshot.is_open(position) {
-                    Some((
-                        State {
-                            position,
-                            time: next_time,
-                        },
-                        1,
-                    ))
-                } else {
-                    None
-                }
-            })
-        },
-    )
-    .next()
-    .unwrap();
+    let (start, end) = (snowfield.start(), snowfield.end());
 
-    end.time
+    snowfield.itinerary(&[start, end]).unwrap().total_time
 }
 
 #[aoc(day24, part2)]
 fn solve_part2(input: &str) -> usize {
     let snowfield = parse_snowfield(input);
-    let start_pos = Point::new(0, snowfield.start_col);
-    let start = State {
-        position: start_pos,
-        time: 0,
-    };
-
-    let (first_end, _) = Dijkstra::new(
-        start,
-        |state| state.position == Point::new(snowfield.max_row + 1, snowfield.end_col),
-        |state| {
-            let next_time = state.time + 1;
-            let snapshot = snowfield.snapshot(next_time);
-
-            [
-                state.position.shift(Direction::Down),
-                state.position.shift(Direction::Right),
-                state.position,
-                state.position.shift(Direction::Up),
-                state.position.shift(Direction::Left),
-            ]
-            .into_iter()
-            .filter_map(move |position| {
-                if snapshot.is_open(position) {
-                    Some((
-                        State {
-                            position,
-                            time: next_time,
-                        },
-                        1,
-                    ))
-                } else {
-                    None
-                }
-            })
-        },
-    )
-    .next()
-    .unwrap();
-
-    let (second_start, _) = Dijkstra::new(
-        first_end,
-        |state| state.position == Point::new(0, snowfield.start_col),
-        |state| {
-            let next_time = state.time + 1;
-            let snapshot = snowfield.snapshot(next_time);
-
-            [
-                state.position.shift(Direction::Down),
-                state.position.shift(Direction::Right),
-                state.position,
-                state.position.shift(Direction::Up),
-                state.position.shift(Direction::Left),
-            ]
-            .into_iter()
-            .filter_map(move |position| {
-                if snapshot.is_open(position) {
-                    Some((
-                        State {
-                            position,
-                            time: next_time,
-                        },
-                        1,
-                    ))
-                } else {
-                    None
-                }
+    let (start, end) = (snowfield.start(), snowfield.end());
+
+    snowfield
+        .itinerary(&[start, end, start, end])
+        .unwrap()
+        .total_time
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_itinerary() {
+        let snowfield = parse_snowfield(
+            "#.######
+#>>.<^<#
+#.<..<<#
+#>v.><>#
+#<^v^^>#
+######.#",
+        );
+        let (start, end) = (snowfield.start(), snowfield.end());
+
+        assert_eq!(
+            snowfield.itinerary(&[start, end, start, end]),
+            Some(Itinerary {
+                total_time: 54,
+                arrivals: vec![18, 41, 54],
             })
-        },
-    )
-    .next()
-    .unwrap();
-
-    let (final_end, _) = Dijkstra::new(
-        second_start,
-        |state| state.position == Point::new(snowfield.max_row + 1, snowfield.end_col),
-        |state| {
-            let next_time = state.time + 1;
-            let snapshot = snowfield.snapshot(next_time);
-
-            [
-                state.position.shift(Direction::Down),
-                state.position.shift(Direction::Right),
-                state.position,
-                state.position.shift(Direction::Up),
-                state.position.shift(Direction::Left),
-            ]
-            .into_iter()
-            .filter_map(move |position| {
-                if snapshot.is_open(position) {
-                    Some((
-                        State {
-                            position,
-                            time: next_time,
-                        },
-                        1,
-                    ))
-                } else {
-                    None
-                }
+        );
+        assert_eq!(
+            snowfield.itinerary(&[start, start]),
+            Some(Itinerary {
+                total_time: 0,
+                arrivals: vec![0],
             })
-        },
-    )
-    .next()
-    .unwrap();
-
-    final_end.time
+        );
+        // Walls can never be reached
+        assert_eq!(snowfield.itinerary(&[start, Point::new(0, 0)]), None);
+    }
 }